@@ -0,0 +1,129 @@
+use crate::vec::Vec3;
+use crate::ray::Ray;
+
+// Axis aligned bounding box, stored as its min and max corners.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min : Vec3,
+    pub max : Vec3,
+}
+
+impl Aabb {
+
+    pub fn new(min : Vec3, max : Vec3) -> Self {
+        Aabb { min, max }
+    }
+
+    // a box that contains nothing, so that surrounding it with any other box returns that box.
+    pub fn empty() -> Self {
+        Aabb {
+            min : Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max : Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points(points : &[Vec3]) -> Self {
+        points.iter().fold(Aabb::empty(), |b, p| Aabb::surrounding(&b, &Aabb::new(*p, *p)))
+    }
+
+    pub fn surrounding(a : &Aabb, b : &Aabb) -> Aabb {
+        Aabb {
+            min : Vec3::new(a.min.x.min(b.min.x), a.min.y.min(b.min.y), a.min.z.min(b.min.z)),
+            max : Vec3::new(a.max.x.max(b.max.x), a.max.y.max(b.max.y), a.max.z.max(b.max.z)),
+        }
+    }
+
+    // makes sure no side is thinner than delta, flat boxes would be missed by rays hitting them edge on
+    pub fn pad(&self, delta : f32) -> Aabb {
+        let mut min = self.min;
+        let mut max = self.max;
+        if max.x - min.x < delta { min.x -= delta * 0.5; max.x += delta * 0.5; }
+        if max.y - min.y < delta { min.y -= delta * 0.5; max.y += delta * 0.5; }
+        if max.z - min.z < delta { min.z -= delta * 0.5; max.z += delta * 0.5; }
+        Aabb { min, max }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    // index of the axis where the box is the widest, 0 = x, 1 = y, 2 = z
+    pub fn longest_axis(&self) -> usize {
+        let d = self.max - self.min;
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    // slab test, returns true if the ray overlaps the box somewhere inside [t_min, t_max]
+    pub fn hit(&self, r : &Ray, mut t_min : f32, mut t_max : f32) -> bool {
+        for axis in 0..3 {
+            let inv_d = 1.0 / r.dir[axis];
+            let mut t0 = (self.min[axis] - r.origin[axis]) * inv_d;
+            let mut t1 = (self.max[axis] - r.origin[axis]) * inv_d;
+
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            // written so NaNs (0 * inf) never shrink the interval
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn aabb_hit(){
+        let b = Aabb::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::one());
+
+        let towards = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(b.hit(&towards, 0.0, f32::INFINITY));
+        assert!(!b.hit(&towards, 0.0, 3.0));
+
+        let away = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(!b.hit(&away, 0.0, f32::INFINITY));
+
+        let parallel = Ray::new(Vec3::new(0.0, 2.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(!b.hit(&parallel, 0.0, f32::INFINITY));
+    }
+
+    #[test]
+    fn aabb_surrounding(){
+        let a = Aabb::new(Vec3::zero(), Vec3::one());
+        let b = Aabb::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::new(0.0, 2.0, 0.5));
+        let s = Aabb::surrounding(&a, &b);
+
+        assert_eq!(s.min, Vec3::new(-1.0, 0.0, 0.0));
+        assert_eq!(s.max, Vec3::new(1.0, 2.0, 1.0));
+        assert_eq!(Aabb::surrounding(&Aabb::empty(), &a), a);
+        assert_eq!(a.surface_area(), 6.0);
+        assert_eq!(Aabb::empty().surface_area(), 0.0);
+    }
+}
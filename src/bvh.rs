@@ -0,0 +1,212 @@
+use crate::aabb::Aabb;
+use crate::ray::Ray;
+use crate::hitrecord::{HitRecord, Hittable};
+
+// cost of visiting a node relative to testing a single primitive, used by the surface area heuristic.
+const TRAVERSAL_COST : f32 = 0.125;
+const MAX_LEAF_SIZE : usize = 4;
+
+type Object = Box<dyn Hittable + Send + Sync>;
+
+// Bounding volume hierarchy over a set of hittables, split using the surface area heuristic (SAH).
+// Can be used anywhere a HittableList is used.
+pub enum BvhNode {
+    Leaf {
+        objects : Vec<Object>,
+        bbox : Aabb,
+    },
+    Interior {
+        left : Box<BvhNode>,
+        right : Box<BvhNode>,
+        bbox : Aabb,
+    },
+}
+
+impl BvhNode {
+
+    pub fn new(objects : Vec<Object>) -> Self {
+        let items = objects.into_iter().map(|o| (o.bounding_box(), o)).collect();
+        BvhNode::build(items)
+    }
+
+    fn build(mut items : Vec<(Aabb, Object)>) -> Self {
+        let bbox = items.iter().fold(Aabb::empty(), |b, (item_box, _)| Aabb::surrounding(&b, item_box));
+
+        if items.len() <= 1 {
+            return BvhNode::leaf(items, bbox);
+        }
+
+        // find the cheapest split along every axis, sweeping the objects sorted by their centroid.
+        let mut best_cost = f32::INFINITY;
+        let mut best_axis = 0;
+        let mut best_split = 0;
+        let mut right_areas = vec![0.0; items.len()];
+
+        for axis in 0..3 {
+            BvhNode::sort_by_axis(&mut items, axis);
+
+            let mut right_box = Aabb::empty();
+            for i in (1..items.len()).rev() {
+                right_box = Aabb::surrounding(&right_box, &items[i].0);
+                right_areas[i] = right_box.surface_area();
+            }
+
+            let mut left_box = Aabb::empty();
+            for split in 1..items.len() {
+                left_box = Aabb::surrounding(&left_box, &items[split - 1].0);
+                let cost = left_box.surface_area() * split as f32
+                         + right_areas[split] * (items.len() - split) as f32;
+
+                if cost < best_cost {
+                    best_cost = cost;
+                    best_axis = axis;
+                    best_split = split;
+                }
+            }
+        }
+
+        let parent_area = bbox.surface_area();
+        let split_cost = if parent_area > 0.0 { TRAVERSAL_COST + best_cost / parent_area } else { f32::INFINITY };
+        let leaf_cost = items.len() as f32;
+
+        if items.len() <= MAX_LEAF_SIZE && leaf_cost <= split_cost {
+            return BvhNode::leaf(items, bbox);
+        }
+
+        // degenerate boxes (all objects on top of each other) fall back to a median split
+        if best_cost == f32::INFINITY || parent_area == 0.0 {
+            best_axis = bbox.longest_axis();
+            best_split = items.len() / 2;
+        }
+
+        BvhNode::sort_by_axis(&mut items, best_axis);
+        let right_items = items.split_off(best_split);
+
+        BvhNode::Interior {
+            left : Box::new(BvhNode::build(items)),
+            right : Box::new(BvhNode::build(right_items)),
+            bbox,
+        }
+    }
+
+    fn leaf(items : Vec<(Aabb, Object)>, bbox : Aabb) -> Self {
+        BvhNode::Leaf {
+            objects : items.into_iter().map(|(_, o)| o).collect(),
+            bbox,
+        }
+    }
+
+    fn sort_by_axis(items : &mut [(Aabb, Object)], axis : usize) {
+        items.sort_by(|a, b| {
+            let ca = a.0.centroid()[axis];
+            let cb = b.0.centroid()[axis];
+            ca.partial_cmp(&cb).unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
+}
+
+impl Hittable for BvhNode {
+
+    fn hit(&self, r : &Ray, t_min : f32, t_max : f32, hit_record : &mut HitRecord ) -> bool{
+        match self {
+            BvhNode::Leaf { objects, bbox } => {
+                if !bbox.hit(r, t_min, t_max) {
+                    return false;
+                }
+
+                let mut hit_anything = false;
+                let mut closest_so_far = t_max;
+                for obj in objects {
+                    if obj.hit(r, t_min, closest_so_far, hit_record) {
+                        hit_anything = true;
+                        closest_so_far = hit_record.t;
+                    }
+                }
+                hit_anything
+            },
+            BvhNode::Interior { left, right, bbox } => {
+                if !bbox.hit(r, t_min, t_max) {
+                    return false;
+                }
+
+                let hit_left = left.hit(r, t_min, t_max, hit_record);
+                let closest_so_far = if hit_left { hit_record.t } else { t_max };
+                let hit_right = right.hit(r, t_min, closest_so_far, hit_record);
+
+                hit_left || hit_right
+            },
+        }
+    }
+
+    fn lights(&self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        match self {
+            BvhNode::Leaf { objects, .. } => objects.iter().flat_map(|obj| obj.lights()).collect(),
+            BvhNode::Interior { left, right, .. } => {
+                let mut lights = left.lights();
+                lights.extend(right.lights());
+                lights
+            },
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self {
+            BvhNode::Leaf { bbox, .. } => *bbox,
+            BvhNode::Interior { bbox, .. } => *bbox,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::vec::Vec3;
+    use crate::geometry::Sphere;
+    use crate::hitrecord::HittableList;
+    use crate::materials::Lambertian;
+
+    fn spheres() -> Vec<Object> {
+        let mut objects : Vec<Object> = Vec::new();
+        for x in -5..5 {
+            for z in -5..5 {
+                let center = Vec3::new(x as f32, (x * z) as f32 * 0.1, z as f32);
+                objects.push( Box::new( Sphere::new(center, 0.3, Box::new( Lambertian::new(Vec3::one()) ))));
+            }
+        }
+        objects
+    }
+
+    #[test]
+    fn bvh_matches_list(){
+        let list = HittableList::new(spheres());
+        let bvh = BvhNode::new(spheres());
+
+        assert_eq!(bvh.bounding_box(), list.bounding_box());
+
+        for i in 0..200 {
+            let a = i as f32 * 0.37;
+            let origin = Vec3::new(a.sin() * 12.0, 3.0, a.cos() * 12.0);
+            let target = Vec3::new((a * 3.1).sin() * 4.0, 0.0, (a * 1.7).cos() * 4.0);
+            let r = Ray::new(origin, target - origin);
+
+            let mut list_rec = HitRecord::new();
+            let mut bvh_rec = HitRecord::new();
+            let list_hit = list.hit(&r, 0.001, f32::INFINITY, &mut list_rec);
+            let bvh_hit = bvh.hit(&r, 0.001, f32::INFINITY, &mut bvh_rec);
+
+            assert_eq!(list_hit, bvh_hit);
+            if list_hit {
+                assert_eq!(list_rec.t, bvh_rec.t);
+                assert_eq!(list_rec.p, bvh_rec.p);
+            }
+        }
+    }
+
+    #[test]
+    fn bvh_empty(){
+        let bvh = BvhNode::new(Vec::new());
+        let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0));
+        assert!(!bvh.hit(&r, 0.001, f32::INFINITY, &mut HitRecord::new()));
+    }
+}
@@ -1,5 +1,7 @@
 use crate::vec::Vec3;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::sampling;
 
 
 pub struct Camera{
@@ -10,8 +12,10 @@ pub struct Camera{
 	
 	u : Vec3,
 	v : Vec3,
-	w : Vec3,
 	lens_radius : f32,
+	// the rays are spread over the time the shutter is open
+	shutter_open : f32,
+	shutter_close : f32,
 }
 
 impl Camera {
@@ -39,24 +43,50 @@ impl Camera {
 
 		let lens_radius = aperture / 2.0;
 
-		let camera = Camera { 
+		Camera { 
 			origin,
 			horizontal,
 			vertical,
 			lower_left_corner,
 			
-			u, v, w,
-			lens_radius
-		};
+			u, v,
+			lens_radius,
+			shutter_open : 0.0,
+			shutter_close : 0.0,
+		}
+	}
 
-		camera
+	pub fn with_shutter(self, open : f32, close : f32) -> Self {
+		Camera { shutter_open : open, shutter_close : close, ..self }
 	}
 
-	pub fn get_ray(&self, s : f32, t : f32 ) -> Ray {
+	pub fn get_ray(&self, s : f32, t : f32, sampler : &mut dyn Sampler ) -> Ray {
 		//Ray::new(self.origin, self.lower_left_corner + self.horizontal * u + self.vertical * v - self.origin)
-		let rd = self.lens_radius * Vec3::random_unit_circle();
-		let offset = self.u * rd.x + self.v * rd.y;
+		let (x, y) = sampling::concentric_disk(sampler.get_2d());
+		let offset = self.lens_radius * (self.u * x + self.v * y);
+
+		let time = self.shutter_open + (self.shutter_close - self.shutter_open) * sampler.get_1d();
 
-		Ray::new(self.origin + offset, self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset )
+		Ray::new_at(self.origin + offset, self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset, time )
 	}
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests{
+	use super::*;
+	use crate::sampler::SamplerKind;
+
+	#[test]
+	fn camera_shutter(){
+		let camera = Camera::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0), 90.0, 2.0, 0.0, 1.0);
+		let mut sampler = SamplerKind::Independent.build(0, 1);
+		sampler.start_sample(0, 0, 0);
+		assert_eq!(camera.get_ray(0.5, 0.5, sampler.as_mut()).time, 0.0);
+
+		let camera = camera.with_shutter(0.25, 0.75);
+		let times : Vec<f32> = (0..100).map(|_| camera.get_ray(0.5, 0.5, sampler.as_mut()).time).collect();
+		assert!(times.iter().all(|t| (0.25..0.75).contains(t)));
+		assert!(times.iter().any(|t| *t < 0.4) && times.iter().any(|t| *t > 0.6));
+	}
+}
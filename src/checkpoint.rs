@@ -0,0 +1,176 @@
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::film::Film;
+use crate::scene::RenderSettings;
+
+const MAGIC : &[u8; 8] = b"RTCKPT\0\0";
+const VERSION : u32 = 4;
+// the scene source and the settings are short, anything longer is a broken file
+const MAX_TEXT_LEN : u32 = 1 << 20;
+
+// Everything needed to carry on with a render: the film, the settings it was rendered with and which scene it
+// is, see Args::scene_source. The random numbers only depend on the seed and sampler in the settings and the
+// sample counts of the film, see Sampler::start_sample, so that's all the generator state there is to keep.
+//
+// The file is the magic, the version, the scene source, the settings as TOML and then the film, see Film::write_to.
+pub struct Checkpoint {
+    pub scene : String,
+    pub settings : RenderSettings,
+    pub film : Film,
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(PathBuf, std::io::Error),
+    Invalid(PathBuf, String),
+}
+
+impl std::fmt::Display for CheckpointError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CheckpointError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            CheckpointError::Invalid(path, message) => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl Checkpoint {
+    // written next to `path` first and then moved over it, so a crash while saving keeps the last checkpoint
+    pub fn save(&self, path : &Path) -> Result<(), CheckpointError> {
+        let io_error = |err| CheckpointError::Io(path.to_path_buf(), err);
+        let settings = toml::to_string(&self.settings)
+            .map_err(|err| CheckpointError::Invalid(path.to_path_buf(), err.to_string()))?;
+
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+
+        let mut out = BufWriter::new(std::fs::File::create(&temp).map_err(io_error)?);
+        out.write_all(MAGIC).map_err(io_error)?;
+        out.write_all(&VERSION.to_le_bytes()).map_err(io_error)?;
+        write_text(&mut out, &self.scene).map_err(io_error)?;
+        write_text(&mut out, &settings).map_err(io_error)?;
+        out.write_all(&(self.film.width as u32).to_le_bytes()).map_err(io_error)?;
+        out.write_all(&(self.film.height as u32).to_le_bytes()).map_err(io_error)?;
+        self.film.write_to(&mut out).map_err(io_error)?;
+        out.into_inner().map_err(|err| io_error(err.into_error()))?.sync_all().map_err(io_error)?;
+
+        std::fs::rename(&temp, path).map_err(io_error)
+    }
+
+    pub fn load(path : &Path) -> Result<Checkpoint, CheckpointError> {
+        let io_error = |err| CheckpointError::Io(path.to_path_buf(), err);
+        let invalid = |message : &str| CheckpointError::Invalid(path.to_path_buf(), message.to_string());
+        let mut input = BufReader::new(std::fs::File::open(path).map_err(io_error)?);
+
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic).map_err(io_error)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+        let version = read_u32(&mut input).map_err(io_error)?;
+        if version != VERSION {
+            return Err(invalid(&format!("checkpoint version {} is not supported", version)));
+        }
+
+        let scene = read_text(&mut input).map_err(io_error)?.ok_or_else(|| invalid("the scene source is broken"))?;
+        let settings = read_text(&mut input).map_err(io_error)?.ok_or_else(|| invalid("the settings are broken"))?;
+        let settings : RenderSettings = toml::from_str(&settings).map_err(|err| invalid(&err.to_string()))?;
+
+        let width = read_u32(&mut input).map_err(io_error)? as usize;
+        let height = read_u32(&mut input).map_err(io_error)? as usize;
+        if (width, height) != (settings.width, settings.height) {
+            return Err(invalid("the film size doesn't match the settings"));
+        }
+        let film = Film::read_from(&mut input, width, height).map_err(io_error)?;
+
+        Ok(Checkpoint { scene, settings, film })
+    }
+}
+
+// a length and then UTF-8
+fn write_text(out : &mut impl Write, text : &str) -> std::io::Result<()> {
+    out.write_all(&(text.len() as u32).to_le_bytes())?;
+    out.write_all(text.as_bytes())
+}
+
+// None when the length is too long or the text isn't UTF-8
+fn read_text(input : &mut impl Read) -> std::io::Result<Option<String>> {
+    let len = read_u32(input)?;
+    if len > MAX_TEXT_LEN {
+        return Ok(None);
+    }
+    let mut text = vec![0; len as usize];
+    input.read_exact(&mut text)?;
+    Ok(String::from_utf8(text).ok())
+}
+
+fn read_u32(input : &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::renderer::{RenderData, render_pass};
+    use crate::renderer::tests::test_scene;
+    use crate::scene::{Scene, AdaptiveSampling};
+    use std::sync::{Arc, RwLock};
+
+    // nothing but the background
+    fn sky(settings : &RenderSettings) -> Arc<RwLock<RenderData>> {
+        let scene = test_scene(settings.width, settings.height, settings.samples_per_pixel, Vec::new());
+        Arc::new(RwLock::new(RenderData::new(Scene { render : settings.clone(), ..scene })))
+    }
+
+    #[test]
+    fn checkpoint_resume(){
+        let settings = RenderSettings {
+            width : 7,
+            height : 5,
+            samples_per_pixel : 3,
+            max_depth : 2,
+            adaptive : Some(AdaptiveSampling { threshold : 0.5, ..AdaptiveSampling::default() }),
+            // the biggest seed the TOML of the settings can hold
+            seed : i64::MAX as u64,
+            ..RenderSettings::default()
+        };
+        let mut film = Film::new(7, 5);
+        render_pass(&sky(&settings), &mut film, 4, 2, 1);
+
+        let path = std::env::temp_dir().join(format!("raytracer-{}-test.checkpoint", std::process::id()));
+        let scene = "preset `random` with seed 3".to_string();
+        Checkpoint { scene : scene.clone(), settings : settings.clone(), film : film.clone() }.save(&path).unwrap();
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(checkpoint.settings, settings);
+        assert!(RenderSettings { seed : u64::MAX, ..settings.clone() }.validate().is_err());
+        assert_eq!(checkpoint.scene, scene);
+        assert_eq!(checkpoint.film.pixels()[12].g, film.pixels()[12].g);
+        assert_eq!(checkpoint.film.aovs(), film.aovs());
+
+        // carries on up to the new sample count
+        let mut film = checkpoint.film;
+        let settings = RenderSettings { adaptive : None, samples_per_pixel : 5, ..settings };
+        assert_eq!(render_pass(&sky(&settings), &mut film, 4, u32::MAX, 1), 3 * 7 * 5);
+        assert!(film.aovs().iter().all(|a| a.samples == 5));
+
+        let garbage = std::env::temp_dir().join(format!("raytracer-{}-garbage.checkpoint", std::process::id()));
+        std::fs::write(&garbage, b"not a checkpoint").unwrap();
+        assert!(matches!(Checkpoint::load(&garbage), Err(CheckpointError::Invalid(..))));
+        // a huge length doesn't get allocated
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend(u32::MAX.to_le_bytes());
+        std::fs::write(&garbage, bytes).unwrap();
+        assert!(matches!(Checkpoint::load(&garbage), Err(CheckpointError::Invalid(..))));
+        std::fs::remove_file(&garbage).unwrap();
+    }
+}
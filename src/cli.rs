@@ -0,0 +1,309 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, CommandFactory};
+use clap::error::ErrorKind;
+
+use crate::presets;
+use crate::scene::{self, Scene, RenderSettings, AdaptiveSampling, Progressive};
+use crate::output::{Aov, OutputFormat, ExrPrecision};
+use crate::tonemap::ToneMapper;
+use crate::sampler::SamplerKind;
+use crate::filter::{self, FilterKind};
+
+/// Ray Tracing in One Weekend, but in rust.
+///
+/// Renders a scene file, or one of the built-in presets, to an image.
+/// Settings given on the command line override the ones from the scene.
+#[derive(Debug, Parser)]
+#[command(name = "raytracer", version)]
+pub struct Args {
+    /// Scene file (.toml) or the name of a built-in preset: random, debug, cornell
+    #[arg(default_value = "random")]
+    pub scene : String,
+
+    /// Image width in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width : Option<u32>,
+
+    /// Image height in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height : Option<u32>,
+
+    /// Samples per pixel
+    #[arg(short = 's', long = "spp", value_parser = clap::value_parser!(u32).range(1..=i32::MAX as i64))]
+    pub samples_per_pixel : Option<u32>,
+
+    /// Sample adaptively: keep sampling a pixel until its relative noise is below this
+    #[arg(long, value_parser = parse_non_negative)]
+    pub adaptive_threshold : Option<f32>,
+
+    /// Fewest samples per pixel with adaptive sampling, turns it on
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub min_spp : Option<u32>,
+
+    /// Most samples per pixel with adaptive sampling, turns it on
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_spp : Option<u32>,
+
+    /// Render in passes of this many samples per pixel, writing the image after every pass
+    #[arg(long, value_name = "SPP", value_parser = clap::value_parser!(u32).range(1..))]
+    pub progressive : Option<u32>,
+
+    /// With progressive passes, only write the image every this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_non_negative)]
+    pub snapshot_interval : Option<f32>,
+
+    /// Maximum number of bounces per ray
+    #[arg(short = 'd', long, value_parser = clap::value_parser!(u32).range(1..=i32::MAX as i64))]
+    pub max_depth : Option<u32>,
+
+    /// Number of render threads [default: number of cores]
+    #[arg(short = 'j', long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub threads : Option<u32>,
+
+    /// Width and height of the tiles the image is split into
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_size : u32,
+
+    /// Run the denoiser on the finished image
+    #[arg(long)]
+    pub denoise : bool,
+
+    /// Output image path
+    #[arg(short, long, default_value = "test.png")]
+    pub output : PathBuf,
+
+    /// Output image format, guessed from the output extension when not given.
+    /// exr, hdr and pfm keep the linear floating point radiance
+    #[arg(short, long, value_enum)]
+    pub format : Option<OutputFormat>,
+
+    /// Exposure adjustment in stops, for 8 bit outputs
+    #[arg(long, allow_negative_numbers = true)]
+    pub exposure : Option<f32>,
+
+    /// Tone mapping curve, for 8 bit outputs
+    #[arg(long, value_enum)]
+    pub tone_mapper : Option<ToneMapper>,
+
+    /// Radiance that maps to white with extended-reinhard and hable
+    #[arg(long)]
+    pub white_point : Option<f32>,
+
+    /// Sample type of OpenEXR output
+    #[arg(long, value_enum, default_value_t = ExrPrecision::Half)]
+    pub exr_precision : ExrPrecision,
+
+    /// Extra images to write next to the colour, comma separated.
+    /// Extra layers of the same file for exr, files named like out.albedo.png otherwise
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub aovs : Vec<Aov>,
+
+    /// Save the render to this file every --checkpoint-interval seconds and when it's done, so it can be resumed
+    #[arg(long, value_name = "FILE")]
+    pub checkpoint : Option<PathBuf>,
+
+    /// Seconds between checkpoints
+    #[arg(long, value_name = "SECONDS", default_value_t = 300.0)]
+    pub checkpoint_interval : f32,
+
+    /// Carry on with the render saved in a checkpoint, with its settings. Pass a higher --spp to add samples.
+    /// The checkpoint keeps being updated unless --checkpoint says otherwise
+    #[arg(long, value_name = "FILE")]
+    pub resume : Option<PathBuf>,
+
+    /// Seed for the procedurally generated presets and the camera samples, up to 2^63 - 1 [default: 0]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(..=i64::MAX as u64))]
+    pub seed : Option<u64>,
+
+    /// Where the random numbers of the samples come from, the low discrepancy ones converge faster [default: independent]
+    #[arg(long, value_enum)]
+    pub sampler : Option<SamplerKind>,
+
+    /// Reconstruction filter that spreads every sample over the pixels around it [default: box]
+    #[arg(long, value_enum)]
+    pub filter : Option<FilterKind>,
+
+    /// Radius of the filter in pixels, from 0.5 to 16. Every filter has its own default
+    #[arg(long, value_name = "PIXELS", value_parser = parse_radius)]
+    pub filter_radius : Option<f32>,
+}
+
+impl Args {
+
+    // exits with a usage error, formatted the same way as the errors clap reports itself
+    pub fn fail(kind : ErrorKind, message : String) -> ! {
+        Args::command().error(kind, message).exit()
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        match self.format.or_else(|| OutputFormat::from_path(&self.output)) {
+            Some(format) => format,
+            None => Args::fail(ErrorKind::InvalidValue,
+                format!("can't guess the image format of `{}`, pass --format", self.output.display())),
+        }
+    }
+
+    // loads the preset or scene file and applies the command line overrides
+    pub fn load_scene(&self) -> Result<Scene, scene::SceneError> {
+        let mut scene = match presets::preset(&self.scene, self.seed.unwrap_or(0)) {
+            Some(scene) => scene,
+            None => {
+                let path = Path::new(&self.scene);
+                if !path.exists() && path.extension().is_none() {
+                    return Err(scene::SceneError::Unknown(self.scene.clone()));
+                }
+                scene::load_scene(path)?
+            },
+        };
+
+        self.apply_render_overrides(&mut scene.render);
+        scene.render.validate().map_err(scene::SceneError::Settings)?;
+
+        let tonemap = &mut scene.tonemap;
+        if let Some(exposure) = self.exposure { tonemap.exposure = exposure; }
+        if let Some(tone_mapper) = self.tone_mapper { tonemap.tone_mapper = tone_mapper; }
+        if let Some(white_point) = self.white_point { tonemap.white_point = white_point; }
+
+        Ok(scene)
+    }
+
+    // what the checkpoints remember of the scene, so a render isn't resumed with another one
+    pub fn scene_source(&self) -> String {
+        if presets::PRESET_NAMES.contains(&self.scene.as_str()) {
+            format!("preset `{}` with seed {}", self.scene, self.seed.unwrap_or(0))
+        } else {
+            let path = Path::new(&self.scene);
+            let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+            format!("`{}`", path.display())
+        }
+    }
+
+    pub fn apply_render_overrides(&self, render : &mut RenderSettings) {
+        if let Some(w) = self.width { render.width = w as usize; }
+        if let Some(h) = self.height { render.height = h as usize; }
+        if let Some(spp) = self.samples_per_pixel { render.samples_per_pixel = spp as i32; }
+        if let Some(depth) = self.max_depth { render.max_depth = depth as i32; }
+        if self.denoise { render.denoise = true; }
+        if self.adaptive_threshold.is_some() || self.min_spp.is_some() || self.max_spp.is_some() {
+            let adaptive = render.adaptive.get_or_insert_with(AdaptiveSampling::default);
+            if let Some(threshold) = self.adaptive_threshold { adaptive.threshold = threshold; }
+            if let Some(min) = self.min_spp { adaptive.min_samples = min; }
+            if let Some(max) = self.max_spp { adaptive.max_samples = max; }
+        }
+        if self.progressive.is_some() || self.snapshot_interval.is_some() {
+            let progressive = render.progressive.get_or_insert_with(Progressive::default);
+            if let Some(spp) = self.progressive { progressive.pass_samples = spp; }
+            if self.snapshot_interval.is_some() { progressive.snapshot_interval = self.snapshot_interval; }
+        }
+        if let Some(seed) = self.seed { render.seed = seed; }
+        if let Some(sampler) = self.sampler { render.sampler = sampler; }
+        if let Some(kind) = self.filter {
+            // the radius of the scene's filter doesn't carry over to another kind
+            if kind != render.filter.kind { render.filter.radius = None; }
+            render.filter.kind = kind;
+        }
+        if self.filter_radius.is_some() { render.filter.radius = self.filter_radius; }
+    }
+}
+
+// thresholds and intervals, NaN and infinity don't mean anything for either
+fn parse_non_negative(value : &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(v) if v >= 0.0 && v.is_finite() => Ok(v),
+        Ok(v) => Err(format!("{} isn't a number 0 or more", v)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_radius(value : &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(radius) if (filter::MIN_RADIUS..=filter::MAX_RADIUS).contains(&radius) => Ok(radius),
+        Ok(radius) => Err(format!("{} isn't between {} and {}", radius, filter::MIN_RADIUS, filter::MAX_RADIUS)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::filter::Filter;
+
+    #[test]
+    fn cli_args(){
+        Args::command().debug_assert();
+
+        let args = Args::try_parse_from(["raytracer", "debug", "--width", "64", "--spp", "2", "-o", "out.jpg"]).unwrap();
+        assert_eq!(args.output_format(), OutputFormat::Jpeg);
+
+        let scene = args.load_scene().unwrap();
+        assert_eq!(scene.render.width, 64);
+        assert_eq!(scene.render.height, 400);
+        assert_eq!(scene.render.samples_per_pixel, 2);
+        assert!(!scene.render.denoise);
+        assert_eq!(scene.render.adaptive, None);
+
+        let args = Args::try_parse_from(["raytracer", "-o", "out", "--format", "exr", "--exr-precision", "float"]).unwrap();
+        assert_eq!(args.output_format(), OutputFormat::Exr);
+        assert_eq!(args.exr_precision, ExrPrecision::Float);
+        assert!(args.aovs.is_empty());
+
+        let args = Args::try_parse_from(["raytracer", "--aovs", "albedo,object-id", "--aovs", "depth"]).unwrap();
+        assert_eq!(args.aovs, [Aov::Albedo, Aov::ObjectId, Aov::Depth]);
+        assert!(Args::try_parse_from(["raytracer", "--aovs", "albedo,colour"]).is_err());
+
+        let args = Args::try_parse_from(["raytracer", "debug", "--exposure", "-1.5", "--tone-mapper", "extended-reinhard"]).unwrap();
+        let scene = args.load_scene().unwrap();
+        assert_eq!(scene.tonemap.exposure, -1.5);
+        assert_eq!(scene.tonemap.tone_mapper, ToneMapper::ExtendedReinhard);
+
+        let args = Args::try_parse_from(["raytracer", "debug", "--denoise"]).unwrap();
+        assert!(args.load_scene().unwrap().render.denoise);
+
+        let args = Args::try_parse_from(["raytracer", "debug", "--max-spp", "256"]).unwrap();
+        let adaptive = args.load_scene().unwrap().render.adaptive.unwrap();
+        assert_eq!((adaptive.min_samples, adaptive.max_samples), (16, 256));
+
+        assert!(Args::try_parse_from(["raytracer", "--seed", "18446744073709551615"]).is_err());
+        let args = Args::try_parse_from(["raytracer", "debug", "--seed", "7", "--resume", "render.checkpoint"]).unwrap();
+        assert_eq!(args.load_scene().unwrap().render.seed, 7);
+        assert_eq!(args.resume, Some(PathBuf::from("render.checkpoint")));
+        assert_eq!(args.scene_source(), "preset `debug` with seed 7");
+        let args = Args::try_parse_from(["raytracer", "--resume", "render.checkpoint"]).unwrap();
+        assert_eq!(args.scene_source(), "preset `random` with seed 0");
+        let args = Args::try_parse_from(["raytracer", "scenes/cornell.toml"]).unwrap();
+        assert_eq!(args.scene_source(), format!("`{}`", std::fs::canonicalize("scenes/cornell.toml").unwrap().display()));
+
+        let args = Args::try_parse_from(["raytracer", "debug", "--sampler", "sobol"]).unwrap();
+        assert_eq!(args.load_scene().unwrap().render.sampler, SamplerKind::Sobol);
+        assert!(Args::try_parse_from(["raytracer", "--sampler", "random"]).is_err());
+
+        let args = Args::try_parse_from(["raytracer", "debug", "--filter", "mitchell"]).unwrap();
+        assert_eq!(args.load_scene().unwrap().render.filter.radius(), 2.0);
+        let args = Args::try_parse_from(["raytracer", "debug", "--filter-radius", "1.5"]).unwrap();
+        assert_eq!(args.load_scene().unwrap().render.filter, Filter { kind : FilterKind::Box, radius : Some(1.5) });
+        assert!(Args::try_parse_from(["raytracer", "--filter-radius", "0.25"]).is_err());
+        assert!(Args::try_parse_from(["raytracer", "--filter-radius", "NaN"]).is_err());
+        assert!(Args::try_parse_from(["raytracer", "--filter-radius", "100"]).is_err());
+
+        let args = Args::try_parse_from(["raytracer", "debug", "--snapshot-interval", "10"]).unwrap();
+        let progressive = args.load_scene().unwrap().render.progressive.unwrap();
+        assert_eq!((progressive.pass_samples, progressive.snapshot_interval), (4, Some(10.0)));
+
+        let args = Args::try_parse_from(["raytracer", "cornel"]).unwrap();
+        let err = args.load_scene().err().unwrap().to_string();
+        assert_eq!(err, "`cornel` is neither a scene file nor a preset (random, debug, cornell)");
+
+        // checked together with the scene's own settings, below the default min_samples
+        let args = Args::try_parse_from(["raytracer", "debug", "--max-spp", "8"]).unwrap();
+        let err = args.load_scene().err().unwrap().to_string();
+        assert_eq!(err, "render.adaptive needs 0 < min_samples <= max_samples, found 16 and 8");
+        assert!(Args::try_parse_from(["raytracer", "--adaptive-threshold", "nan"]).is_err());
+        assert!(Args::try_parse_from(["raytracer", "--snapshot-interval", "-1"]).is_err());
+        assert!(Args::try_parse_from(["raytracer", "--progressive", "0"]).is_err());
+
+        assert!(Args::try_parse_from(["raytracer", "--spp", "0"]).is_err());
+        assert!(Args::try_parse_from(["raytracer", "--threads", "-1"]).is_err());
+    }
+}
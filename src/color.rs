@@ -9,12 +9,13 @@ impl Color<u8>{
 
     pub fn new_from_f32( r: f32, g : f32, b : f32 ) -> Self{
         Color{ 
-            r: (r * 255.0) as u8,
-            g: (g * 255.0) as u8,
-            b: (b * 255.0) as u8,
+            r: (r * 255.0).round() as u8,
+            g: (g * 255.0).round() as u8,
+            b: (b * 255.0).round() as u8,
         }   
     }
 
+    #[allow(dead_code)]
     pub fn black() -> Self{
         Color{
             r:  0,
@@ -48,4 +49,14 @@ impl Color<u8>{
     }
 }
 
+// linear radiance, not limited to [0, 1]
+impl Color<f32>{
+
+    pub fn new( r: f32, g : f32, b : f32 ) -> Self{
+        Color{ r, g, b }
+    }
 
+    pub fn zero() -> Self{
+        Color{ r: 0.0, g : 0.0, b: 0.0 }
+    }
+}
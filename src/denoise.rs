@@ -0,0 +1,143 @@
+use crate::color::Color;
+use crate::film::{Aovs, Film};
+use crate::vec::Vec3;
+
+// Joint bilateral filter over the film, guided by the AOVs instead of the noisy colour: neighbours only
+// count if they see a surface with a similar albedo, normal and depth. The colour is divided by the albedo
+// before filtering and multiplied back after, so textures stay sharp and only the lighting gets smoothed.
+pub struct Denoiser {
+    // the filter covers (2 * radius + 1)^2 pixels
+    pub radius : usize,
+    // in pixels
+    pub sigma_spatial : f32,
+    pub sigma_albedo : f32,
+    // on 1 - cos of the angle between the normals
+    pub sigma_normal : f32,
+    // relative to the depth of the centre pixel
+    pub sigma_depth : f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            radius : 6,
+            sigma_spatial : 4.0,
+            sigma_albedo : 0.1,
+            sigma_normal : 0.1,
+            sigma_depth : 0.05,
+        }
+    }
+}
+
+// keeps the division by the albedo from blowing up on dark surfaces
+const MIN_ALBEDO : f32 = 0.01;
+
+impl Denoiser {
+    pub fn denoise(&self, film : &mut Film) {
+        let (width, height) = (film.width, film.height);
+        let aovs = film.aovs();
+
+        let irradiance : Vec<Vec3> = film.pixels().iter().zip(aovs).map(|(p, a)| {
+            let albedo = demodulation(a);
+            Vec3::new(p.r / albedo.x, p.g / albedo.y, p.b / albedo.z)
+        }).collect();
+
+        let r = self.radius as isize;
+        let mut filtered = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let index = x + y * width;
+                let center = &aovs[index];
+
+                let mut sum = Vec3::zero();
+                let mut weights = 0.0;
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        let (nx, ny) = (x as isize + dx, y as isize + dy);
+                        if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+                            continue;
+                        }
+
+                        let other = nx as usize + ny as usize * width;
+                        let distance = (dx * dx + dy * dy) as f32;
+                        let w = (-distance / (2.0 * self.sigma_spatial * self.sigma_spatial)).exp() * self.guide_weight(center, &aovs[other]);
+                        sum = sum + irradiance[other] * w;
+                        weights += w;
+                    }
+                }
+
+                // the centre pixel always has a weight of 1
+                let albedo = demodulation(center);
+                let c = sum / weights * albedo;
+                filtered.push(Color::new(c.x, c.y, c.z));
+            }
+        }
+
+        film.pixels_mut().clone_from_slice(&filtered);
+    }
+
+    // how much the guides of two pixels agree, between 0 and 1
+    fn guide_weight(&self, a : &Aovs, b : &Aovs) -> f32 {
+        // the background only blends with the background
+        match (a.depth.is_finite(), b.depth.is_finite()) {
+            (false, false) => return 1.0,
+            (true, true) => (),
+            _ => return 0.0,
+        }
+
+        let albedo = (a.albedo - b.albedo).length_squared() / (2.0 * self.sigma_albedo * self.sigma_albedo);
+        let normal = (1.0 - Vec3::dot(&a.normal, &b.normal)).max(0.0) / self.sigma_normal;
+        let depth = (a.depth - b.depth) / (a.depth.max(1e-6) * self.sigma_depth);
+        (-(albedo + normal + 0.5 * depth * depth)).exp()
+    }
+}
+
+fn demodulation(a : &Aovs) -> Vec3 {
+    Vec3::new(a.albedo.x.max(MIN_ALBEDO), a.albedo.y.max(MIN_ALBEDO), a.albedo.z.max(MIN_ALBEDO))
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::renderer::{Tile, RunningStats};
+
+    #[test]
+    fn denoise_flat_regions(){
+        // two flat walls side by side with noisy lighting
+        let (w, h) = (16, 8);
+        let mut tile = Tile::new(0, 0, w, h);
+        let wall = |x : usize| if x < w / 2 { Vec3::new(0.8, 0.2, 0.2) } else { Vec3::new(0.2, 0.2, 0.8) };
+        tile.data = (0..w * h).map(|i| {
+            let noise = if (i * 7919) % 3 == 0 { 1.6 } else { 0.7 };
+            let c = wall(i % w) * noise;
+            Color::new(c.x, c.y, c.z)
+        }).collect();
+        tile.aovs = (0..w * h).map(|i| Aovs {
+            albedo : wall(i % w),
+            normal : Vec3::new(0.0, 0.0, 1.0),
+            depth : 2.0,
+            object_id : 1,
+            samples : 4,
+            ..Aovs::default()
+        }).collect();
+        tile.stats = vec![RunningStats::new(); w * h];
+        tile.weights = vec![1.0; w * h];
+
+        let mut film = Film::new(w, h);
+        film.add_tile(&tile);
+        let before = film.pixels().to_vec();
+        Denoiser::default().denoise(&mut film);
+
+        let spread = |pixels : &[Color<f32>]| {
+            let reds : Vec<f32> = pixels.iter().take(w / 2).map(|p| p.r).collect();
+            reds.iter().cloned().fold(f32::MIN, f32::max) - reds.iter().cloned().fold(f32::MAX, f32::min)
+        };
+        assert!(spread(film.pixels()) < 0.25 * spread(&before));
+
+        // the walls don't bleed into each other
+        for p in film.pixels() {
+            assert!(p.r > 2.0 * p.b || p.b > 2.0 * p.r, "{:?}", p);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::io::{Read, Write};
+
+use crate::color::Color;
+use crate::vec::Vec3;
+use crate::renderer::{Tile, RunningStats};
+use crate::tonemap::ToneMapping;
+
+// pixels with less filter weight than this have no colour yet
+const MIN_WEIGHT : f32 = 1e-3;
+
+// Extra per pixel outputs, from the first hit of the camera rays and averaged over the samples that hit something.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aovs {
+    pub albedo : Vec3,
+    pub normal : Vec3,
+    pub position : Vec3,
+    // distance from the camera, infinite when nothing was hit
+    pub depth : f32,
+    // object seen by the first sample, objects are numbered from 1 and 0 is the background
+    pub object_id : u32,
+    pub samples : u32,
+}
+
+impl Default for Aovs {
+    fn default() -> Self {
+        Aovs {
+            albedo : Vec3::zero(),
+            normal : Vec3::zero(),
+            position : Vec3::zero(),
+            depth : f32::INFINITY,
+            object_id : 0,
+            samples : 0,
+        }
+    }
+}
+
+impl Aovs {
+    // both sets of samples together, weighted by their sample counts
+    fn merge(&self, other : &Aovs) -> Aovs {
+        let samples = self.samples + other.samples;
+        if self.samples == 0 || other.samples == 0 {
+            return Aovs { samples, ..if self.samples == 0 { *other } else { *self } };
+        }
+
+        let a = self.samples as f32 / samples as f32;
+        let b = 1.0 - a;
+        let normal = self.normal * a + other.normal * b;
+        let mut merged = Aovs {
+            albedo : self.albedo * a + other.albedo * b,
+            normal : if normal.length_squared() > 0.0 { Vec3::normalize(normal) } else { normal },
+            samples,
+            ..*self
+        };
+        // the position only means something where the depth is finite
+        match (self.depth.is_finite(), other.depth.is_finite()) {
+            (true, true) => {
+                merged.position = self.position * a + other.position * b;
+                merged.depth = self.depth * a + other.depth * b;
+            },
+            (false, true) => {
+                merged.position = other.position;
+                merged.depth = other.depth;
+            },
+            _ => (),
+        }
+        merged
+    }
+}
+
+// The whole image in linear f32 radiance. Tiles are added in as they finish, their filter weighted samples
+// summed up with what is already there so the same pixels can be rendered again in more passes. Nothing is
+// clamped or quantized until the image is written out.
+#[derive(Clone)]
+pub struct Film {
+    pub width : usize,
+    pub height : usize,
+    // weighted sum over weight sum, kept up to date as tiles are added
+    pixels : Vec<Color<f32>>,
+    weighted : Vec<Color<f32>>,
+    weights : Vec<f32>,
+    aovs : Vec<Aovs>,
+    // of the luminance of all the samples so far, to know when a pixel is done
+    stats : Vec<RunningStats>,
+}
+
+impl Film {
+    pub fn new(width : usize, height : usize) -> Self {
+        Film {
+            width,
+            height,
+            pixels : vec![Color::zero(); width * height],
+            weighted : vec![Color::zero(); width * height],
+            weights : vec![0.0; width * height],
+            aovs : vec![Aovs::default(); width * height],
+            stats : vec![RunningStats::new(); width * height],
+        }
+    }
+
+    // lets the tile carry on sampling where the film is, instead of starting from scratch
+    pub fn prepare_tile(&self, tile : &mut Tile) {
+        tile.stats.clear();
+        for y in 0..tile.h {
+            let row = (tile.y + y) * self.width + tile.x;
+            tile.stats.extend_from_slice(&self.stats[row..row + tile.w]);
+        }
+    }
+
+    // adds the new samples of the tile into the film
+    pub fn add_tile(&mut self, tile : &Tile) {
+        // the filtered samples reach a bit past the tile, into its neighbours
+        let (x0, y0, w, h) = tile.splat_bounds(self.width, self.height);
+        for y in 0..h {
+            for x in 0..w {
+                let i = (y0 + y) * self.width + x0 + x;
+                let j = y * w + x;
+
+                let (s, t) = (&self.weighted[i], &tile.data[j]);
+                self.weighted[i] = Color::new(s.r + t.r, s.g + t.g, s.b + t.b);
+                self.weights[i] += tile.weights[j];
+                self.pixels[i] = self.resolve(i);
+            }
+        }
+
+        for y in 0..tile.h {
+            for x in 0..tile.w {
+                let i = (tile.y + y) * self.width + tile.x + x;
+                let j = y * tile.w + x;
+
+                if tile.aovs[j].samples == 0 {
+                    continue;
+                }
+                self.aovs[i] = self.aovs[i].merge(&tile.aovs[j]);
+                // the tile stats already include the film ones, see prepare_tile
+                self.stats[i] = tile.stats[j];
+            }
+        }
+    }
+
+    // the negative lobes of some filters can leave a pixel with next to no weight, or less than none,
+    // dividing by that would blow the pixel up so it's black then
+    fn resolve(&self, i : usize) -> Color<f32> {
+        let (s, w) = (&self.weighted[i], self.weights[i]);
+        if w <= MIN_WEIGHT {
+            return Color::zero();
+        }
+        Color::new(s.r / w, s.g / w, s.b / w)
+    }
+
+    // row by row, starting at the bottom of the image
+    pub fn pixels(&self) -> &[Color<f32>] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Color<f32>] {
+        &mut self.pixels
+    }
+
+    pub fn aovs(&self) -> &[Aovs] {
+        &self.aovs
+    }
+
+    // everything in the film as little endian numbers, pixel by pixel, for checkpoints
+    pub fn write_to(&self, out : &mut impl Write) -> std::io::Result<()> {
+        for (((p, w), a), s) in self.weighted.iter().zip(&self.weights).zip(&self.aovs).zip(&self.stats) {
+            let floats = [
+                p.r, p.g, p.b, *w,
+                a.albedo.x, a.albedo.y, a.albedo.z,
+                a.normal.x, a.normal.y, a.normal.z,
+                a.position.x, a.position.y, a.position.z,
+                a.depth, s.mean, s.m2,
+            ];
+            for f in floats {
+                out.write_all(&f.to_le_bytes())?;
+            }
+            for u in [a.object_id, a.samples, s.count] {
+                out.write_all(&u.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    // the other way around from write_to
+    pub fn read_from(input : &mut impl Read, width : usize, height : usize) -> std::io::Result<Film> {
+        let mut film = Film::new(width, height);
+        for i in 0..width * height {
+            let mut f = [0.0; 16];
+            for v in f.iter_mut() {
+                *v = read_f32(input)?;
+            }
+            let (object_id, samples, count) = (read_u32(input)?, read_u32(input)?, read_u32(input)?);
+
+            film.weighted[i] = Color::new(f[0], f[1], f[2]);
+            film.weights[i] = f[3];
+            film.pixels[i] = film.resolve(i);
+            film.aovs[i] = Aovs {
+                albedo : Vec3::new(f[4], f[5], f[6]),
+                normal : Vec3::new(f[7], f[8], f[9]),
+                position : Vec3::new(f[10], f[11], f[12]),
+                depth : f[13],
+                object_id,
+                samples,
+            };
+            film.stats[i] = RunningStats { count, mean : f[14], m2 : f[15] };
+        }
+        Ok(film)
+    }
+
+    // 8 bit sRGB, ready for image::ImageBuffer
+    pub fn to_rgb8(&self, tonemap : &ToneMapping) -> Vec<u8> {
+        self.pixels.iter().flat_map(|p| {
+            let c = tonemap.apply(p);
+            [c.r, c.g, c.b]
+        }).collect()
+    }
+}
+
+fn read_f32(input : &mut impl Read) -> std::io::Result<f32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_u32(input : &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn film_tiles(){
+        let mut film = Film::new(3, 2);
+
+        let mut tile = Tile::new(1, 0, 2, 2);
+        tile.data = vec![Color::new(0.25, 1.0, 4.0), Color::new(1.0, 0.0, -1.0), Color::zero(), Color::new(0.01, 0.0, 0.0)];
+        tile.weights = vec![1.0; 4];
+        tile.aovs = vec![Aovs { samples : 1, ..Aovs::default() }; 4];
+        tile.aovs[3].object_id = 7;
+        tile.stats = vec![RunningStats::new(); 4];
+        film.add_tile(&tile);
+
+        assert_eq!(film.pixels()[1].b, 4.0);
+        assert_eq!(film.pixels()[5].r, 0.01);
+        assert_eq!(film.aovs()[5].object_id, 7);
+
+        let rgb = film.to_rgb8(&ToneMapping::default());
+        assert_eq!(rgb.len(), 3 * 2 * 3);
+        assert_eq!(&rgb[3..9], &[137, 255, 255, 255, 0, 0]);
+        assert_eq!(&rgb[15..18], &[25, 0, 0]);
+
+        // a second pass with 3 samples of another colour
+        let mut tile = Tile::new(1, 0, 1, 1);
+        tile.data = vec![Color::new(3.0, 3.0, 0.0)];
+        tile.weights = vec![3.0];
+        tile.aovs = vec![Aovs { samples : 3, depth : 2.0, object_id : 9, ..Aovs::default() }];
+        tile.stats = vec![RunningStats::new()];
+        film.add_tile(&tile);
+        let p = &film.pixels()[1];
+        assert_eq!((p.r, p.g, p.b), (0.8125, 1.0, 1.0));
+        let aovs = film.aovs()[1];
+        assert_eq!((aovs.samples, aovs.depth, aovs.object_id), (4, 2.0, 0));
+
+        // next to no weight, or a negative one, from the lobes of a wide filter
+        let mut tile = Tile::new(0, 0, 2, 1);
+        tile.data = vec![Color::new(1e-6, 0.0, 0.0), Color::new(-1.0, 0.0, 0.0)];
+        tile.weights = vec![1e-6, -0.5];
+        tile.aovs = vec![Aovs::default(); 2];
+        tile.stats = vec![RunningStats::new(); 2];
+        let mut film = Film::new(2, 1);
+        film.add_tile(&tile);
+        assert!(film.pixels().iter().all(|p| (p.r, p.g, p.b) == (0.0, 0.0, 0.0)));
+    }
+}
@@ -0,0 +1,130 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    // every sample only counts for the pixel it's in, with the default radius
+    #[default]
+    Box,
+    Tent,
+    Gaussian,
+    // B = C = 1/3, sharper than the gaussian, slightly negative between half the radius and the radius
+    Mitchell,
+    // windowed sinc, the sharpest and the most ringing
+    Lanczos,
+}
+
+// smaller filters would leave parts of the pixels without samples, and much bigger ones only blur
+pub const MIN_RADIUS : f32 = 0.5;
+pub const MAX_RADIUS : f32 = 16.0;
+
+impl FilterKind {
+    pub fn default_radius(&self) -> f32 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+// How the samples are spread over the pixels around them. Every sample is added to all the pixels within
+// `radius` of it, weighted by the filter at its distance from their centres, and every pixel ends up
+// as the weighted average of what it got.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filter {
+    pub kind : FilterKind,
+    // in pixels, each kind has its own default
+    pub radius : Option<f32>,
+}
+
+impl Filter {
+    pub fn radius(&self) -> f32 {
+        self.radius.unwrap_or_else(|| self.kind.default_radius())
+    }
+
+    // pixels the samples reach past the one they're in
+    pub fn reach(&self) -> usize {
+        (self.radius() - 0.5).ceil().max(0.0) as usize
+    }
+
+    // weight of a sample dx, dy pixels away from a pixel centre
+    pub fn evaluate(&self, dx : f32, dy : f32) -> f32 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x : f32) -> f32 {
+        let radius = self.radius();
+        let x = x.abs();
+        if x > radius {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - x / radius,
+            FilterKind::Gaussian => {
+                // the tail is cut off so the filter goes to 0 at the radius
+                let sigma = radius / 3.0;
+                let gaussian = |x : f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            },
+            FilterKind::Mitchell => mitchell(2.0 * x / radius, 1.0 / 3.0, 1.0 / 3.0),
+            FilterKind::Lanczos => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+// Mitchell-Netravali cubic, for x in [0, 2]
+fn mitchell(x : f32, b : f32, c : f32) -> f32 {
+    let (x2, x3) = (x * x, x * x * x);
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x3 + (-18.0 + 12.0 * b + 6.0 * c) * x2 + (6.0 - 2.0 * b)) / 6.0
+    } else {
+        ((-b - 6.0 * c) * x3 + (6.0 * b + 30.0 * c) * x2 + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+    }
+}
+
+fn sinc(x : f32) -> f32 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    let px = std::f32::consts::PI * x;
+    px.sin() / px
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn filter_values(){
+        let kinds = [FilterKind::Box, FilterKind::Tent, FilterKind::Gaussian, FilterKind::Mitchell, FilterKind::Lanczos];
+        for kind in kinds {
+            let filter = Filter { kind, radius : None };
+            let r = filter.radius();
+            // peaks in the middle, symmetric and nothing past the radius
+            assert!(filter.evaluate(0.0, 0.0) > 0.0);
+            assert!(filter.evaluate(0.0, 0.0) >= filter.evaluate(0.3, 0.1));
+            assert_eq!(filter.evaluate(0.4, -0.2), filter.evaluate(-0.4, 0.2));
+            assert_eq!(filter.evaluate(r + 0.01, 0.0), 0.0);
+            if kind != FilterKind::Box {
+                assert!(filter.evaluate(r, 0.0).abs() < 1e-4, "{:?} at the radius", kind);
+            }
+        }
+
+        assert_eq!(Filter::default().reach(), 0);
+        assert_eq!(Filter { kind : FilterKind::Tent, radius : None }.reach(), 1);
+        assert_eq!(Filter { kind : FilterKind::Mitchell, radius : Some(2.5) }.reach(), 2);
+        // the Mitchell filter adds up to 1 over its support, and goes negative
+        let mitchell = Filter { kind : FilterKind::Mitchell, radius : Some(2.0) };
+        let sum : f32 = (0..400).map(|i| mitchell.evaluate_1d(-2.0 + (i as f32 + 0.5) * 0.01) * 0.01).sum();
+        assert!((sum - 1.0).abs() < 1e-3);
+        assert!(mitchell.evaluate_1d(1.5) < 0.0);
+    }
+}
@@ -2,6 +2,10 @@ use crate::vec::Vec3;
 use crate::materials::{Material};
 use crate::hitrecord::{HitRecord, Hittable};
 use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::sampler::Sampler;
+use crate::sampling;
+
 pub struct Sphere {
     pub center : Vec3,
     pub radius : f32,
@@ -13,42 +17,552 @@ impl Sphere{
         Sphere{
             center,
             radius,
-            material : material, 
+            material, 
         }
     }
 }
 
 
+// shared by Sphere and MovingSphere, with the centre where it is at the time of the ray
+fn hit_sphere(center : Vec3, radius : f32, material : &(dyn Material + Send + Sync), r : &Ray, t_min : f32, t_max : f32, hit_record : &mut HitRecord ) -> bool{
+    let oc = r.origin - center;
+    let a = r.dir.length_squared();
+    let half_b = Vec3::dot(&oc, &r.dir);
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+
+    if discriminant < 0.0
+    { 
+        return false;
+    }
+
+    let sqrtd = discriminant.sqrt();
+
+    let mut root = (-half_b - sqrtd) / a;
+    if root < t_min || t_max < root {
+        root = (-half_b + sqrtd) / a;
+
+        if root < t_min || t_max < root {
+            return false;
+        }
+    }
+
+    hit_record.t = root;
+    hit_record.p = r.at(root);
+    let normal = (hit_record.p - center) / radius;
+    hit_record.set_face_normal(r, &normal);
+    (hit_record.u, hit_record.v) = sphere_uv(&((hit_record.p - center) / radius.abs()));
+    hit_record.material =  Some(material.clone_box());
+    true
+}
+
 impl Hittable for Sphere {
     
     fn hit(&self, r : &Ray, t_min : f32, t_max : f32, hit_record : &mut HitRecord ) -> bool{
-        let oc = r.origin - self.center;
-        let a = r.dir.length_squared();
-        let half_b = Vec3::dot(&oc, &r.dir);
-        let c = oc.length_squared() - self.radius * self.radius;
-        let discriminant = half_b * half_b - a * c;
-
-        if discriminant < 0.0
-        { 
-            return false;
+        hit_sphere(self.center, self.radius, self.material.as_ref(), r, t_min, t_max, hit_record)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius.abs(), self.radius.abs(), self.radius.abs());
+        Aabb::new(self.center - r, self.center + r)
+    }
+
+    // directions are sampled uniformly inside the cone the sphere covers as seen from origin
+    fn pdf_value(&self, origin : &Vec3, direction : &Vec3) -> f32 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(*origin, *direction), 0.001, f32::INFINITY, &mut rec) {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center - *origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            // inside the sphere, points are picked uniformly over its area instead, the pdf of the unit
+            // sphere spread over this one
+            let cosine = Vec3::dot(&rec.normal, &Vec3::normalize(*direction)).abs();
+            let area_pdf = sampling::uniform_sphere_pdf() / radius_squared;
+            return (rec.p - *origin).length_squared() * area_pdf / cosine;
         }
 
-        let sqrtd = discriminant.sqrt();
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        sampling::uniform_cone_pdf(cos_theta_max)
+    }
 
-        let mut root = (-half_b - sqrtd) / a;
-        if root < t_min || t_max < root {
-            root = (-half_b + sqrtd) / a;
+    fn random(&self, origin : &Vec3, sampler : &mut dyn Sampler) -> Option<Vec3> {
+        let u = sampler.get_2d();
 
-            if root < t_min || t_max < root {
-                return false;
-            }
+        let to_center = self.center - *origin;
+        let distance_squared = to_center.length_squared();
+        let radius_squared = self.radius * self.radius;
+
+        if distance_squared <= radius_squared {
+            let point = self.center + self.radius.abs() * sampling::uniform_sphere(u);
+            return Some(point - *origin);
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        Some(sampling::to_world(sampling::uniform_cone(u, cos_theta_max), &Vec3::normalize(to_center)))
+    }
+
+    fn lights(&self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        if !self.material.is_emissive() {
+            return Vec::new();
+        }
+        vec![ Box::new( Sphere::new(self.center, self.radius, self.material.clone_box()) ) ]
+    }
+}
+
+
+// A sphere whose centre moves in a straight line from center0 at time0 to center1 at time1, and stays
+// put before and after that. The lights are sampled without knowing the time of the ray, so it isn't one
+// of them, an emissive one only lights what bounces into it.
+pub struct MovingSphere {
+    pub center0 : Vec3,
+    pub center1 : Vec3,
+    pub time0 : f32,
+    pub time1 : f32,
+    pub radius : f32,
+    pub material : Box<dyn Material + Send + Sync>,
+}
+
+impl MovingSphere {
+    pub fn center(&self, time : f32) -> Vec3 {
+        if self.time1 == self.time0 {
+            return self.center0;
+        }
+        let t = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center0 + t * (self.center1 - self.center0)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, r : &Ray, t_min : f32, t_max : f32, hit_record : &mut HitRecord ) -> bool{
+        hit_sphere(self.center(r.time), self.radius, self.material.as_ref(), r, t_min, t_max, hit_record)
+    }
+
+    // everywhere it goes between time0 and time1
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius.abs(), self.radius.abs(), self.radius.abs());
+        let box0 = Aabb::new(self.center0 - r, self.center0 + r);
+        let box1 = Aabb::new(self.center1 - r, self.center1 + r);
+        Aabb::surrounding(&box0, &box1)
+    }
+}
+
+// Spherical mapping of a point on the unit sphere, u goes around the y axis starting at -x
+// and v goes from the bottom pole to the top one.
+fn sphere_uv(p : &Vec3) -> (f32, f32) {
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z).atan2(p.x) + std::f32::consts::PI;
+    (phi / (2.0 * std::f32::consts::PI), theta / std::f32::consts::PI)
+}
+
+// Watertight ray/triangle intersection (Woop, Benthin and Wald 2013), rays hitting a shared edge
+// or vertex of two triangles always hit one of them. Returns the ray parameter and the barycentric
+// weights of p0, p1 and p2.
+pub fn intersect_triangle(r : &Ray, p0 : Vec3, p1 : Vec3, p2 : Vec3, t_min : f32, t_max : f32) -> Option<(f32, [f32; 3])> {
+    // permute the axes so the ray travels along z
+    let kz = if r.dir.x.abs() > r.dir.y.abs() && r.dir.x.abs() > r.dir.z.abs() { 0 }
+             else if r.dir.y.abs() > r.dir.z.abs() { 1 }
+             else { 2 };
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if r.dir[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    if r.dir[kz] == 0.0 {
+        return None;
+    }
+
+    // shear so the ray direction becomes (0, 0, 1)
+    let sx = r.dir[kx] / r.dir[kz];
+    let sy = r.dir[ky] / r.dir[kz];
+    let sz = 1.0 / r.dir[kz];
+
+    let a = p0 - r.origin;
+    let b = p1 - r.origin;
+    let c = p2 - r.origin;
+
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+
+    let mut e0 = cx * by - cy * bx;
+    let mut e1 = ax * cy - ay * cx;
+    let mut e2 = bx * ay - by * ax;
+
+    // edges that are exactly zero in single precision get recomputed in double precision
+    if e0 == 0.0 || e1 == 0.0 || e2 == 0.0 {
+        e0 = (cx as f64 * by as f64 - cy as f64 * bx as f64) as f32;
+        e1 = (ax as f64 * cy as f64 - ay as f64 * cx as f64) as f32;
+        e2 = (bx as f64 * ay as f64 - by as f64 * ax as f64) as f32;
+    }
+
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+
+    let det = e0 + e1 + e2;
+    if det == 0.0 {
+        return None;
+    }
+
+    let az = sz * a[kz];
+    let bz = sz * b[kz];
+    let cz = sz * c[kz];
+    let t = (e0 * az + e1 * bz + e2 * cz) / det;
+
+    if t < t_min || t_max < t {
+        return None;
+    }
+
+    Some((t, [e0 / det, e1 / det, e2 / det]))
+}
+
+// fills the hit record for a triangle hit, interpolating the vertex normals and texcoords when there are any.
+// Without texcoords (u, v) are the barycentric weights of the second and third vertex.
+pub fn set_triangle_hit(r : &Ray, t : f32, positions : [Vec3; 3], barycentric : [f32; 3], normals : Option<[Vec3; 3]>, texcoords : Option<[[f32; 2]; 3]>, hit_record : &mut HitRecord) {
+    let [b0, b1, b2] = barycentric;
+    let geometric_normal = Vec3::normalize(Vec3::cross(&(positions[1] - positions[0]), &(positions[2] - positions[0])));
+
+    hit_record.t = t;
+    hit_record.p = positions[0] * b0 + positions[1] * b1 + positions[2] * b2;
+    hit_record.set_face_normal(r, &geometric_normal);
+
+    if let Some(n) = normals {
+        let mut shading_normal = Vec3::normalize(n[0] * b0 + n[1] * b1 + n[2] * b2);
+        // keep the shading normal on the same side as the face we hit
+        if Vec3::dot(&shading_normal, &hit_record.normal) < 0.0 {
+            shading_normal = -shading_normal;
+        }
+        hit_record.normal = shading_normal;
+    }
+
+    (hit_record.u, hit_record.v) = match texcoords {
+        Some(uv) => (
+            uv[0][0] * b0 + uv[1][0] * b1 + uv[2][0] * b2,
+            uv[0][1] * b0 + uv[1][1] * b1 + uv[2][1] * b2,
+        ),
+        None => (b1, b2),
+    };
+}
+
+pub struct Triangle {
+    pub vertices : [Vec3; 3],
+    pub normals : Option<[Vec3; 3]>,
+    pub texcoords : Option<[[f32; 2]; 3]>,
+    pub material : Box<dyn Material + Send + Sync>,
+}
+
+impl Triangle {
+    pub fn new(v0 : Vec3, v1 : Vec3, v2 : Vec3, material : Box<dyn Material + Send + Sync>) -> Self {
+        Triangle {
+            vertices : [v0, v1, v2],
+            normals : None,
+            texcoords : None,
+            material,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn with_normals(vertices : [Vec3; 3], normals : [Vec3; 3], material : Box<dyn Material + Send + Sync>) -> Self {
+        Triangle {
+            vertices,
+            normals : Some(normals),
+            texcoords : None,
+            material,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+
+    fn hit(&self, r : &Ray, t_min : f32, t_max : f32, hit_record : &mut HitRecord ) -> bool{
+        let [p0, p1, p2] = self.vertices;
+
+        match intersect_triangle(r, p0, p1, p2, t_min, t_max) {
+            Some((t, barycentric)) => {
+                set_triangle_hit(r, t, self.vertices, barycentric, self.normals, self.texcoords, hit_record);
+                hit_record.material = Some(self.material.clone_box());
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points(&self.vertices).pad(1e-4)
+    }
+
+    // points are sampled uniformly over the area, the pdf is converted to solid angle
+    fn pdf_value(&self, origin : &Vec3, direction : &Vec3) -> f32 {
+        let [p0, p1, p2] = self.vertices;
+        let r = Ray::new(*origin, *direction);
+
+        match intersect_triangle(&r, p0, p1, p2, 0.001, f32::INFINITY) {
+            Some((t, _)) => {
+                let normal = Vec3::cross(&(p1 - p0), &(p2 - p0));
+                let area = 0.5 * normal.length();
+                let distance_squared = t * t * direction.length_squared();
+                let cosine = Vec3::dot(&Vec3::normalize(normal), &Vec3::normalize(*direction)).abs();
+                if cosine == 0.0 || area == 0.0 { 0.0 } else { distance_squared * sampling::uniform_triangle_pdf(area) / cosine }
+            },
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin : &Vec3, sampler : &mut dyn Sampler) -> Option<Vec3> {
+        let [b0, b1, b2] = sampling::uniform_triangle(sampler.get_2d());
+        let [p0, p1, p2] = self.vertices;
+        Some(p0 * b0 + p1 * b1 + p2 * b2 - *origin)
+    }
+
+    fn lights(&self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        if !self.material.is_emissive() {
+            return Vec::new();
+        }
+        vec![ Box::new( Triangle {
+            vertices : self.vertices,
+            normals : self.normals,
+            texcoords : self.texcoords,
+            material : self.material.clone_box(),
+        } ) ]
+    }
+}
+
+// Parallelogram with one corner at q and sides u and v, made of two triangles.
+// Textures are mapped over the whole parallelogram, (0, 0) at q and (1, 1) at q + u + v.
+pub fn quad(q : Vec3, u : Vec3, v : Vec3, material : Box<dyn Material + Send + Sync>) -> Vec<Box<dyn Hittable + Send + Sync>> {
+    let first = Triangle {
+        vertices : [q, q + u, q + u + v],
+        normals : None,
+        texcoords : Some([[0.0, 0.0], [1.0, 0.0], [1.0, 1.0]]),
+        material : material.clone_box(),
+    };
+    let second = Triangle {
+        vertices : [q, q + u + v, q + v],
+        normals : None,
+        texcoords : Some([[0.0, 0.0], [1.0, 1.0], [0.0, 1.0]]),
+        material,
+    };
+    vec![ Box::new(first), Box::new(second) ]
+}
+
+// Axis aligned box between the corners a and b, made of 6 quads.
+pub fn block(a : Vec3, b : Vec3, material : Box<dyn Material + Send + Sync>) -> Vec<Box<dyn Hittable + Send + Sync>> {
+    let min = Vec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+    let max = Vec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+
+    let dx = Vec3::new(max.x - min.x, 0.0, 0.0);
+    let dy = Vec3::new(0.0, max.y - min.y, 0.0);
+    let dz = Vec3::new(0.0, 0.0, max.z - min.z);
+
+    let mut sides = Vec::with_capacity(12);
+    sides.extend( quad(Vec3::new(min.x, min.y, max.z), dx, dy, material.clone_box()) );  // front
+    sides.extend( quad(Vec3::new(max.x, min.y, max.z), -dz, dy, material.clone_box()) ); // right
+    sides.extend( quad(Vec3::new(max.x, min.y, min.z), -dx, dy, material.clone_box()) ); // back
+    sides.extend( quad(Vec3::new(min.x, min.y, min.z), dz, dy, material.clone_box()) );  // left
+    sides.extend( quad(Vec3::new(min.x, max.y, max.z), dx, -dz, material.clone_box()) ); // top
+    sides.extend( quad(Vec3::new(min.x, min.y, min.z), dx, dz, material) );              // bottom
+    sides
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::sampler::SamplerKind;
+    use crate::hitrecord::HittableList;
+
+    fn gray() -> Box<dyn Material + Send + Sync> {
+        Box::new( Lambertian::new(Vec3::new(0.5, 0.5, 0.5)) )
+    }
+
+    #[test]
+    fn sphere_uv(){
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, -2.0), 1.0, gray());
+        let mut rec = HitRecord::new();
+
+        // facing +z, a quarter of the way around from -x
+        assert!(sphere.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.u - 0.25).abs() < 1e-5 && (rec.v - 0.5).abs() < 1e-5, "{} {}", rec.u, rec.v);
+
+        // the top pole
+        assert!(sphere.hit(&Ray::new(Vec3::new(0.0, 5.0, -2.0), Vec3::new(0.0, -1.0, 0.0)), 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.v - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn moving_sphere_hit(){
+        let sphere = MovingSphere {
+            center0 : Vec3::new(-2.0, 0.0, -5.0),
+            center1 : Vec3::new(2.0, 0.0, -5.0),
+            time0 : 0.0,
+            time1 : 1.0,
+            radius : 1.0,
+            material : gray(),
+        };
+        assert_eq!(sphere.center(0.25), Vec3::new(-1.0, 0.0, -5.0));
+        assert_eq!(sphere.center(2.0), sphere.center1);
+
+        let mut rec = HitRecord::new();
+        let towards = |x : f32, time : f32| Ray::new_at(Vec3::new(x, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), time);
+        assert!(sphere.hit(&towards(-2.0, 0.0), 0.001, f32::INFINITY, &mut rec));
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(!sphere.hit(&towards(-2.0, 1.0), 0.001, f32::INFINITY, &mut rec));
+        assert!(sphere.hit(&towards(2.0, 1.0), 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.p - Vec3::new(2.0, 0.0, -4.0)).length() < 1e-5);
+
+        // the box covers the whole way, so the BVH finds it at any time in between
+        let bbox = sphere.bounding_box();
+        assert_eq!((bbox.min, bbox.max), (Vec3::new(-3.0, -1.0, -6.0), Vec3::new(3.0, 1.0, -4.0)));
+        let bvh = crate::bvh::BvhNode::new(vec![Box::new(sphere), Box::new(Sphere::new(Vec3::new(0.0, 5.0, -5.0), 1.0, gray()))]);
+        for i in 0..=10 {
+            let time = i as f32 / 10.0;
+            assert!(bvh.hit(&towards(-2.0 + 4.0 * time, time), 0.001, f32::INFINITY, &mut rec), "missed at {}", time);
+        }
+    }
+
+    #[test]
+    fn triangle_hit(){
+        let tri = Triangle::new(Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0), gray());
+
+        let r = Ray::new(Vec3::new(0.0, 0.0, -2.0), Vec3::new(0.0, 0.0, 1.0));
+        let mut rec = HitRecord::new();
+        assert!(tri.hit(&r, 0.001, f32::INFINITY, &mut rec));
+        assert_eq!(rec.t, 2.0);
+        assert_eq!(rec.p, Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, -1.0));
+        assert!(!rec.front_face);
+
+        let miss = Ray::new(Vec3::new(2.0, 0.0, -2.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(!tri.hit(&miss, 0.001, f32::INFINITY, &mut rec));
+    }
+
+    #[test]
+    fn triangle_barycentric(){
+        let (p0, p1, p2) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let r = Ray::new(Vec3::new(0.25, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let (t, b) = intersect_triangle(&r, p0, p1, p2, 0.0, f32::INFINITY).unwrap();
+        assert_eq!(t, 1.0);
+        assert!((b[0] - 0.25).abs() < 1e-6);
+        assert!((b[1] - 0.25).abs() < 1e-6);
+        assert!((b[2] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn triangle_watertight_shared_edge(){
+        // two triangles forming a quad, rays aimed exactly at the diagonal must always hit one of them
+        let (a, b, c, d) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        for i in 0..=100 {
+            let s = i as f32 / 100.0;
+            let origin = Vec3::new(0.3, 0.7, 1.0);
+            let r = Ray::new(origin, Vec3::new(s, s, 0.0) - origin);
+
+            let first = intersect_triangle(&r, a, b, c, 0.0, f32::INFINITY);
+            let second = intersect_triangle(&r, a, c, d, 0.0, f32::INFINITY);
+            assert!(first.is_some() || second.is_some(), "ray {} leaked through the edge", i);
+        }
+    }
+
+    // integrates the pdf over all directions, which should give 1, and checks random() lands on the shape
+    fn check_light_sampling(light : &dyn Hittable, origin : Vec3){
+        let mut sampler = SamplerKind::Independent.build(0, 1);
+        sampler.start_sample(0, 0, 0);
+        let n = 200_000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let dir = sampling::uniform_sphere(sampler.get_2d());
+            sum += light.pdf_value(&origin, &dir) as f64 / sampling::uniform_sphere_pdf() as f64;
+        }
+        let integral = sum / n as f64;
+        assert!((integral - 1.0).abs() < 0.03, "pdf integrates to {}", integral);
+
+        for _ in 0..1000 {
+            let dir = light.random(&origin, sampler.as_mut()).unwrap();
+            assert!(light.pdf_value(&origin, &dir) > 0.0);
         }
+    }
+
+    #[test]
+    fn sphere_light_sampling(){
+        let sphere = Sphere::new(Vec3::new(0.0, 2.0, 0.0), 1.0, gray());
+        check_light_sampling(&sphere, Vec3::zero());
+        check_light_sampling(&sphere, Vec3::new(0.0, 2.3, 0.2));
+    }
+
+    #[test]
+    fn triangle_light_sampling(){
+        let tri = Triangle::new(Vec3::new(-1.0, 1.0, -1.0), Vec3::new(1.0, 1.0, -1.0), Vec3::new(0.0, 1.5, 1.0), gray());
+        check_light_sampling(&tri, Vec3::zero());
+
+        // no lights to pick from, the shadow ray is skipped instead of panicking
+        let mut sampler = SamplerKind::Independent.build(0, 1);
+        sampler.start_sample(0, 0, 0);
+        assert!(HittableList::new(Vec::new()).random(&Vec3::zero(), sampler.as_mut()).is_none());
+    }
+
+    #[test]
+    fn triangle_interpolated_normal(){
+        let n = Vec3::normalize(Vec3::new(1.0, 0.0, 1.0));
+        let tri = Triangle::with_normals(
+            [Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)],
+            [n, n, n],
+            gray());
 
-        hit_record.t = root;
-        hit_record.p = r.at(root);
-        let normal = (hit_record.p - self.center) / self.radius;
-        hit_record.set_face_normal(r, &normal);
-        hit_record.material =  Some(self.material.clone_box());
-        return true;
+        let r = Ray::new(Vec3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::new();
+        assert!(tri.hit(&r, 0.001, f32::INFINITY, &mut rec));
+        assert!(rec.front_face);
+        assert!((rec.normal - n).length() < 1e-6);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn quad_hit(){
+        let sides = quad(Vec3::new(0.0, 0.0, -1.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), gray());
+        let mut rec = HitRecord::new();
+
+        // texcoords run along u and v over both triangles
+        let inside = |x : f32, y : f32| Ray::new(Vec3::new(x, y, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(sides.iter().any(|s| s.hit(&inside(1.5, 0.25), 0.001, f32::INFINITY, &mut rec)));
+        assert!((rec.u - 0.75).abs() < 1e-5 && (rec.v - 0.25).abs() < 1e-5);
+        assert!(sides.iter().any(|s| s.hit(&inside(0.5, 0.75), 0.001, f32::INFINITY, &mut rec)));
+        assert!((rec.u - 0.25).abs() < 1e-5 && (rec.v - 0.75).abs() < 1e-5);
+        assert!(!sides.iter().any(|s| s.hit(&inside(2.5, 0.5), 0.001, f32::INFINITY, &mut rec)));
+    }
+
+    #[test]
+    fn block_hit(){
+        let sides = block(Vec3::new(1.0, 1.0, 1.0), Vec3::new(-1.0, 0.0, -1.0), gray());
+        assert_eq!(sides.len(), 12);
+        let bbox = sides.iter().fold(Aabb::empty(), |b, s| Aabb::surrounding(&b, &s.bounding_box()));
+        assert!((bbox.min - Vec3::new(-1.0, 0.0, -1.0)).length() < 1e-3 && (bbox.max - Vec3::one()).length() < 1e-3);
+
+        // from outside every side is hit at the same distance, facing the ray
+        let directions = [
+            Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0),
+        ];
+        let center = Vec3::new(0.0, 0.5, 0.0);
+        for dir in directions {
+            let extent = if dir.y != 0.0 { 0.5 } else { 1.0 };
+            let r = Ray::new(center - dir * (extent + 2.0), dir);
+            let mut rec = HitRecord::new();
+            let mut closest = f32::INFINITY;
+            for side in &sides {
+                if side.hit(&r, 0.001, closest, &mut rec) {
+                    closest = rec.t;
+                }
+            }
+            assert!((closest - 2.0).abs() < 1e-4, "{:?} hit at {}", dir, closest);
+            assert!(rec.front_face && (rec.normal + dir).length() < 1e-5);
+        }
+    }
+}
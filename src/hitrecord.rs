@@ -1,6 +1,8 @@
 use crate::vec::Vec3;
 use crate::ray::Ray;
 use crate::materials::{Material};
+use crate::aabb::Aabb;
+use crate::sampler::Sampler;
 
 #[derive(Clone)]
 pub struct HitRecord{
@@ -8,6 +10,11 @@ pub struct HitRecord{
     pub   normal     : Vec3,
     pub   t          : f32,
     pub   front_face : bool,
+    // surface coordinates, for textures
+    pub   u          : f32,
+    pub   v          : f32,
+    // set by Identified, 0 when the object has no id
+    pub   object_id  : u32,
     pub   material   : Option<std::boxed::Box<dyn Material>>,
 }
 
@@ -19,12 +26,15 @@ impl HitRecord{
             normal : Vec3::zero(),
             t : 0.0,
             front_face : true,
+            u : 0.0,
+            v : 0.0,
+            object_id : 0,
             material : None,
         }
     }
     pub fn set_face_normal(&mut self, r : &Ray, outward_normal : &Vec3){
 
-        self.front_face = Vec3::dot(&r.dir, &outward_normal) < 0.0;
+        self.front_face = Vec3::dot(&r.dir, outward_normal) < 0.0;
         self.normal = if self.front_face  { *outward_normal } else { *outward_normal * -1.0 }
         
     }
@@ -32,8 +42,28 @@ impl HitRecord{
 
 pub trait Hittable{
     fn hit(&self, ray : &Ray, t_min : f32, t_max : f32, hit_record : &mut HitRecord ) -> bool;
+    fn bounding_box(&self) -> Aabb;
+
+    // Light sampling, only shapes that can be lights implement these, anything that returns itself
+    // from `lights` has to.
+    // pdf (over solid angle) of `random` picking `direction` when looking from `origin`.
+    fn pdf_value(&self, _origin : &Vec3, _direction : &Vec3) -> f32 {
+        0.0
+    }
+
+    // a direction from `origin` towards a random point on the shape, None when it can't be sampled
+    // and the light is only found by the rays that hit it
+    fn random(&self, _origin : &Vec3, _sampler : &mut dyn Sampler) -> Option<Vec3> {
+        None
+    }
+
+    // copies of the emissive shapes inside this hittable, to be sampled as lights
+    fn lights(&self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        Vec::new()
+    }
 }
 
+// A plain list of hittables, also used as the set of lights where one is picked at random.
 pub struct HittableList {
     objects : Vec<Box<dyn Hittable + Send + Sync>>,
 }
@@ -47,13 +77,38 @@ impl Hittable for HittableList{
         let mut closest_so_far = t_max;
 
         for obj in &self.objects{
-            if obj.hit(&r, t_min, closest_so_far, &mut temp_rec) {
+            if obj.hit(r, t_min, closest_so_far, &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
                 *hit_record = temp_rec.clone();
             }
         }
-        return hit_anything;
+        hit_anything
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.objects.iter().fold(Aabb::empty(), |b, obj| Aabb::surrounding(&b, &obj.bounding_box()))
+    }
+
+    // every object is picked with the same probability
+    fn pdf_value(&self, origin : &Vec3, direction : &Vec3) -> f32 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum : f32 = self.objects.iter().map(|obj| obj.pdf_value(origin, direction)).sum();
+        sum / self.objects.len() as f32
+    }
+
+    fn random(&self, origin : &Vec3, sampler : &mut dyn Sampler) -> Option<Vec3> {
+        if self.objects.is_empty() {
+            return None;
+        }
+        let index = ((sampler.get_1d() * self.objects.len() as f32) as usize).min(self.objects.len() - 1);
+        self.objects[index].random(origin, sampler)
+    }
+
+    fn lights(&self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        self.objects.iter().flat_map(|obj| obj.lights()).collect()
     }
 }
 
@@ -65,4 +120,65 @@ impl HittableList{
             objects
         } 
     }
-}
\ No newline at end of file
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+
+// Tags the hits of an object with an id, so they can be told apart in the object id AOV.
+pub struct Identified {
+    pub id : u32,
+    pub object : Box<dyn Hittable + Send + Sync>,
+}
+
+impl Hittable for Identified {
+    fn hit(&self, r : &Ray, t_min : f32, t_max : f32, hit_record : &mut HitRecord ) -> bool{
+        if self.object.hit(r, t_min, t_max, hit_record) {
+            hit_record.object_id = self.id;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.object.bounding_box()
+    }
+
+    fn pdf_value(&self, origin : &Vec3, direction : &Vec3) -> f32 {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin : &Vec3, sampler : &mut dyn Sampler) -> Option<Vec3> {
+        self.object.random(origin, sampler)
+    }
+
+    fn lights(&self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        self.object.lights()
+    }
+}
+
+// objects placed more than once, like the meshes of a scene file
+impl<H : Hittable + ?Sized> Hittable for std::sync::Arc<H> {
+    fn hit(&self, r : &Ray, t_min : f32, t_max : f32, hit_record : &mut HitRecord ) -> bool{
+        (**self).hit(r, t_min, t_max, hit_record)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+
+    fn pdf_value(&self, origin : &Vec3, direction : &Vec3) -> f32 {
+        (**self).pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin : &Vec3, sampler : &mut dyn Sampler) -> Option<Vec3> {
+        (**self).random(origin, sampler)
+    }
+
+    fn lights(&self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        (**self).lights()
+    }
+}
@@ -4,237 +4,172 @@ use std::sync::*;
 use std::time::Instant;
 
 mod color;
+mod film;
 mod vec;
 mod ray;
 mod geometry;
 mod renderer;
 mod materials;
+mod texture;
+mod noise;
 mod hitrecord;
-use renderer::{RenderData, Tile};
+mod aabb;
+mod bvh;
+mod mesh;
+mod obj;
+mod scene;
+use renderer::RenderData;
 
-use vec::Vec3;
 mod camera;
-use camera::Camera;
+mod presets;
+mod cli;
+mod output;
+mod tonemap;
+mod denoise;
+mod checkpoint;
+mod rng;
+mod sampler;
+mod sampling;
+mod filter;
+mod transform;
+
+use clap::Parser;
+
+// samples per pixel between checkpoints, when the render isn't progressive
+const CHECKPOINT_PASS_SAMPLES : u32 = 16;
 
+fn main() {
+    
+    let start = Instant::now();
 
-use crate::materials::{Lambertian, Metal, Dieletric};
-use crate::geometry::Sphere;
-use crate::hitrecord::Hittable;
-
-use rand::Rng;
-
-fn create_random_scene() -> Vec<Box<dyn Hittable + Send + Sync>> {
-    let mut objects : Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
-
-    // floor
-    let ground_material = Box::new( Lambertian{ albedo :  Vec3::new(0.5, 0.5, 0.5) } );
-    objects.push( Box::new( Sphere::new( Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground_material )));
-
-    let mut rng = rand::thread_rng();
-    for a in -11..11 {
-        for b in -11..11 {
-            
-            let chose_mat = rng.gen::<f32>();
-
-            let center = Vec3::new( (a as f32) + 0.9 * rng.gen::<f32>(), 0.2, b as f32 + 0.9 * rng.gen::<f32>() );
-
-            if (center - Vec3::new(4.0, 0.2, 0.0) ).length() > 0.9 {
-
-                if chose_mat < 0.8 {
-
-                    let color = Vec3::random() * Vec3::random();
-
-                    let sphere = Sphere::new( 
-                        center,
-                        0.2, 
-                        Box::new( Lambertian{ albedo : color } ));
-
-                    objects.push( Box::new(sphere) );  
-                }
-                else if chose_mat < 0.95 {
-                    
-                    let color = Vec3::random_in_range(0.5, 1.0);
-                    let fuzz = rng.gen_range(0.0..0.5);
-                    let sphere = Sphere::new( 
-                        center,
-                        0.2, 
-                        Box::new( Metal{ 
-                                            albedo : color,
-                                            fuzz }
-                                ));
-
-                    objects.push( Box::new(sphere) );  
-                }
-                else
-                {
-                    let color = Vec3::random() * Vec3::random();
+    let args = cli::Args::parse();
+    let format = args.output_format();
 
-                    let sphere = Sphere::new( 
-                        center,
-                        0.2, 
-                        Box::new( Dieletric{ ir : 1.5 } ));
+    let mut scene = match args.load_scene() {
+        Ok(scene) => scene,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    };
 
-                    objects.push( Box::new(sphere) );
-                }
+    // a resumed render keeps its settings, except for the ones given on the command line
+    let scene_source = args.scene_source();
+    let resumed = args.resume.as_ref().map(|path| {
+        let checkpoint = match checkpoint::Checkpoint::load(path) {
+            Ok(checkpoint) => checkpoint,
+            Err(err) => {
+                eprintln!("error: could not resume: {}", err);
+                std::process::exit(1);
             }
+        };
+        if checkpoint.scene != scene_source {
+            eprintln!("error: could not resume: {} is a render of {}, not {}", path.display(), checkpoint.scene, scene_source);
+            std::process::exit(1);
         }
-
-
-
-
-    }
-
-    objects.push( Box::new( Sphere::new( Vec3::new(0.0, 1.0, 0.0),1.0, Box::new( Dieletric{ ir : 1.5 } ) )));      
-    objects.push( Box::new( Sphere::new( Vec3::new(-4.0, 1.0, 0.0), 1.0, Box::new( Lambertian{ albedo : Vec3::new( 0.4, 0.2, 0.1 ) })   )));  
-    objects.push( Box::new( Sphere::new( Vec3::new(4.0, 1.0, 0.0), 1.0, Box::new( Metal{ albedo :  Vec3::new(0.7, 0.6, 0.5), fuzz : 0.0 } ) )));  
-
-    objects
-}
-
-fn create_debug_scene() -> Vec<Box<dyn Hittable + Send + Sync>> {
-    let mut objects : Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
-
-    let floor_material = Box::new( Lambertian{ albedo :  Vec3::new(0.9, 0.9, 0.1) } );
-    // floor 
-    objects.push( Box::new( Sphere::new( Vec3::new(0.0, -100.5, -1.0), 100.0, floor_material.clone()  )));
-
-    objects.push( Box::new( Sphere::new( Vec3::new(-1.0, 0.0, -1.0),0.5, Box::new( Dieletric{ ir : 0.9 } ) )));  
-    objects.push( Box::new( Sphere::new( Vec3::new(0.0, 0.0, -1.0), 0.5, Box::new( Lambertian{ albedo :  Vec3::new(0.4, 0.4, 0.4) } ) )));  
-    objects.push( Box::new( Sphere::new( Vec3::new(1.0, 0.0, -1.0), 0.5, Box::new( Metal{ albedo :  Vec3::new(0.9, 0.8, 0.4), fuzz : 0.9 } ) )));  
-
-    objects
-}
-
-
-
-fn main() {
-    
-
-    let start = Instant::now();
-    let w = 1500;
-    let h = 750;
-  
-
-
-    let camera = {
-
-        let look_from = Vec3::new(7.0,5.4,-5.0);
-        let look_at = Vec3::new(0.0,0.0,0.0);
-        let vup = Vec3::new(0.0, 1.0, 0.0);
-        let dist_to_focus = 5.0;
-        
-        Camera::new( 
-            look_from,
-            look_at,
-            vup,
-            20.0, 
-            w  as f32 / h as f32,
-            0.6,
-            dist_to_focus,
-        )
+        scene.render = checkpoint.settings.clone();
+        args.apply_render_overrides(&mut scene.render);
+        if let Err(err) = scene.render.validate() {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+        if (scene.render.width, scene.render.height) != (checkpoint.film.width, checkpoint.film.height) {
+            eprintln!("error: the size of a resumed render can't change");
+            std::process::exit(1);
+        }
+        println!("Resuming {}", path.display());
+        checkpoint
+    });
+    let checkpoint_path = args.checkpoint.clone().or_else(|| args.resume.clone());
+
+    let settings = output::OutputSettings {
+        format,
+        tonemap : scene.tonemap,
+        exr_precision : args.exr_precision,
+        aovs : args.aovs.clone(),
     };
-
-
+    let w = scene.render.width;
+    let h = scene.render.height;
+    let denoise = scene.render.denoise;
+    let progressive = scene.render.progressive;
+    let render_settings = scene.render.clone();
 
     let render_data  = std::sync::Arc::new( 
         RwLock::new(
-            RenderData::new( w, h, w as f32 / h as f32, 10, 50, camera, create_random_scene() )
+            RenderData::new(scene)
     )); 
 
-    let num_of_tiles = 6;
-    let single_tile_width = w/num_of_tiles;
-    let single_tile_height = h/num_of_tiles;
- 
-    let mut tiles : Vec<Tile> = Vec::new();
-    let mut data : Vec<u8> = Vec::new();
-    data.resize(w * h * 3, 0);
-
-    for x in 0.. ((w + single_tile_width) / single_tile_width){
-        for y in 0.. ((h + single_tile_height) / single_tile_height){
-
-            // calculate x and y position from each tile
-            let tile_x_pos = x * single_tile_width;
-            let tile_y_pos = y * single_tile_height;
-            let mut tile_width = single_tile_width;
-            let mut tile_height = single_tile_height;
-
-            // if the tile size does not match the size of the image, we need to write a tile with "left over"
-            if tile_x_pos + single_tile_width > w {
-                let delta = (tile_x_pos + single_tile_width) - w;
-                tile_width = single_tile_width - delta;
-            }
-
-            if tile_y_pos + single_tile_height > h {
-                let delta = (tile_y_pos + single_tile_height) - h;
-                tile_height = single_tile_height - delta;
-            }
-
-            if tile_width == 0 || tile_height == 0 {
-                continue;
-            }
+    let tile_size = args.tile_size as usize;
+    let mut film = match resumed {
+        Some(checkpoint) => checkpoint.film,
+        None => film::Film::new(w, h),
+    };
 
-            let tile = Tile::new(
-                             tile_x_pos, 
-                             tile_y_pos,
-                             tile_width,
-                             tile_height,
-                        );
-            
-            tiles.push(tile);
+    let num_of_threads = match args.threads {
+        Some(threads) => threads as usize,
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    println!("Rendering {} tiles of {}x{} with {} threads", renderer::make_tiles(w, h, tile_size).len(), tile_size, tile_size, num_of_threads);
+
+    let write_image = |film : &film::Film| {
+        let result = if denoise {
+            let mut denoised = film.clone();
+            denoise::Denoiser::default().denoise(&mut denoised);
+            output::save(&denoised, &args.output, &settings)
+        } else {
+            output::save(film, &args.output, &settings)
+        };
+
+        if let Err(err) = result {
+            eprintln!("error: could not write {}: {}", args.output.display(), err);
+            std::process::exit(1);
         }
-    }
+    };
 
-    println!("number of tiles: {}, tile height: {}", tiles.len(), single_tile_height);
-    let num_of_threads = {
-        let num = 4;
-        if tiles.len() < num {
-            tiles.len()
-        }else{
-            num
+    // a checkpoint that can't be saved isn't worth losing the render over, the image is still written
+    let save_checkpoint = |film : &film::Film| {
+        if let Some(path) = &checkpoint_path {
+            let checkpoint = checkpoint::Checkpoint { scene : scene_source.clone(), settings : render_settings.clone(), film : film.clone() };
+            if let Err(err) = checkpoint.save(path) {
+                eprintln!("warning: could not save the checkpoint: {}", err);
+            }
         }
     };
 
-    let part = tiles.len() / num_of_threads;
-    let mut handles = Vec::with_capacity(num_of_threads);
-    
-    println!("Rendering with {} threads, groups: {}", num_of_threads, part);
-
-    for _ in 0..num_of_threads {
-
-        //split the work per thread, theres a bug where not all tiles get passed throught the tiles.. need to figure out a way to add the left over from the division into the tile jobs.
-        let mut thread_tiles = Vec::with_capacity(part);
-        for _ in 0..part {
-            thread_tiles.push( tiles.pop().unwrap() );
-            println!("len: {}", tiles.len()); 
-        }
+    // checkpoints need the render split in passes too, but without snapshots
+    let pass_samples = match progressive {
+        Some(progressive) => progressive.pass_samples,
+        None if checkpoint_path.is_some() => CHECKPOINT_PASS_SAMPLES,
+        None => u32::MAX,
+    };
 
-        let w = render_data.clone();
-        let j = std::thread::spawn(move || {
-            let mut i = 0; 
-            for t in  &mut thread_tiles{
-                t.run(&w);
-                i = i + 1;
+    // the snapshots overwrite the output, so the render can be stopped at any point
+    let mut last_snapshot = Instant::now();
+    let mut last_checkpoint = Instant::now();
+    let mut passes = 0;
+    while renderer::render_pass(&render_data, &mut film, tile_size, pass_samples, num_of_threads) > 0 {
+        passes += 1;
+
+        if let Some(progressive) = progressive {
+            println!("pass {} done after {}ms", passes, start.elapsed().as_millis());
+            let due = progressive.snapshot_interval.is_none_or(|seconds| last_snapshot.elapsed().as_secs_f32() >= seconds);
+            if due {
+                write_image(&film);
+                last_snapshot = Instant::now();
             }
-            return thread_tiles;
-        });
-
-        handles.push(j);
-    }
+        }
 
-    for k in handles {
-       let rendered_tiles =  k.join().unwrap();
-       for t in rendered_tiles{
-            t.write_data(&mut data, w, h);
+        if last_checkpoint.elapsed().as_secs_f32() >= args.checkpoint_interval {
+            save_checkpoint(&film);
+            last_checkpoint = Instant::now();
         }
     }
+    save_checkpoint(&film);
 
     let render_duration = start.elapsed().as_millis();
     println!("1- program took: {}ms", render_duration);
 
-    let mut img : image::ImageBuffer<image::Rgb<u8>, Vec<u8>> = image::ImageBuffer::from_raw(w as u32, h as u32, data).ok_or("Error Creating buffer").unwrap();
-    img = image::imageops::resize(&img, ((w as f32) * 1.) as u32, ((h as f32) * 1.0) as u32, image::imageops::FilterType::Lanczos3);
-    img = image::imageops::flip_vertical(&img);
-    img.save("test.png").unwrap();
+    write_image(&film);
     
     println!("2- writing file to disk took: {}ms", start.elapsed().as_millis() - render_duration);
 }
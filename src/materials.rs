@@ -1,43 +1,124 @@
 use crate::vec::Vec3;
 use crate::ray::Ray;
 use crate::hitrecord::HitRecord;
-use rand::Rng;
+use crate::texture::{Texture, SolidColor};
+use crate::sampler::Sampler;
+use crate::sampling;
+use std::sync::Arc;
 
-pub trait Material : MaterialClone {
-   fn scatter(&self, r_in : &Ray, rec : &HitRecord, attenuation : &mut Vec3, scattered : &mut Ray) -> bool;
+// A direction picked by Material::scatter.
+// The path throughput is multiplied by value / pdf, for specular bounces value is the attenuation and pdf is 1.
+#[derive(Debug, Copy, Clone)]
+pub struct ScatterRecord {
+    pub direction : Vec3,
+    // bsdf times the cosine with the normal
+    pub value : Vec3,
+    // over solid angle
+    pub pdf : f32,
+    // mirror like bounces (delta distributions), eval and pdf can't be used for them
+    pub is_specular : bool,
+}
+
+// Directions are unit vectors pointing away from the surface, `wo` towards the viewer and `wi` towards the light.
+pub trait Material : MaterialClone + Send + Sync {
+   // picks a new direction for the path, None if the ray is absorbed
+   fn scatter(&self, r_in : &Ray, rec : &HitRecord, sampler : &mut dyn Sampler) -> Option<ScatterRecord>;
+
+   // bsdf times the cosine between wi and the normal, black for specular materials
+   fn eval(&self, _rec : &HitRecord, _wi : &Vec3, _wo : &Vec3) -> Vec3 {
+       Vec3::zero()
+   }
+
+   // pdf of scatter() picking wi when looking from wo, 0 for specular materials
+   fn pdf(&self, _rec : &HitRecord, _wi : &Vec3, _wo : &Vec3) -> f32 {
+       0.0
+   }
+
+   // light given off by the surface, black for anything that isn't a light
+   fn emitted(&self, _rec : &HitRecord) -> Vec3 {
+       Vec3::zero()
+   }
+
+   // objects with an emissive material get sampled directly as lights
+   fn is_emissive(&self) -> bool {
+       false
+   }
+
+   // base colour of the surface, for the albedo AOV
+   fn albedo(&self, _rec : &HitRecord) -> Vec3 {
+       Vec3::one()
+   }
 }
 
 #[derive(Clone)]
 pub struct Lambertian { 
-    pub albedo : Vec3,
+    pub albedo : Arc<dyn Texture>,
+}
+
+impl Lambertian {
+    pub fn new(albedo : Vec3) -> Self {
+        Lambertian{ albedo : SolidColor::shared(albedo) }
+    }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in : &Ray, rec : &HitRecord, attenuation : &mut Vec3, scattered : &mut Ray) -> bool{
-        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();    
-        
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
+    fn scatter(&self, r_in : &Ray, rec : &HitRecord, sampler : &mut dyn Sampler) -> Option<ScatterRecord> {
+        let wi = Vec3::normalize(sampling::to_world(sampling::cosine_hemisphere(sampler.get_2d()), &rec.normal));
+        let wo = -Vec3::normalize(r_in.dir);
+        let pdf = self.pdf(rec, &wi, &wo);
+        if pdf <= 0.0 {
+            return None;
         }
-        
-        *scattered = Ray::new(rec.p, scatter_direction); 
-        *attenuation = self.albedo; 
-        return true;
+
+        Some(ScatterRecord {
+            direction : wi,
+            value : self.eval(rec, &wi, &wo),
+            pdf,
+            is_specular : false,
+        })
+    }
+
+    fn eval(&self, rec : &HitRecord, wi : &Vec3, _wo : &Vec3) -> Vec3 {
+        let cosine = Vec3::dot(&rec.normal, wi).max(0.0);
+        self.albedo.value(rec.u, rec.v, &rec.p) * (cosine / std::f32::consts::PI)
+    }
+
+    // cosine weighted
+    fn pdf(&self, rec : &HitRecord, wi : &Vec3, _wo : &Vec3) -> f32 {
+        sampling::cosine_hemisphere_pdf(Vec3::dot(&rec.normal, wi))
+    }
+
+    fn albedo(&self, rec : &HitRecord) -> Vec3 {
+        self.albedo.value(rec.u, rec.v, &rec.p)
     }
 }
 
 #[derive(Clone)]
 pub struct Metal {
-    pub albedo : Vec3,
+    pub albedo : Arc<dyn Texture>,
     pub fuzz : f32,
 }
 
+impl Metal {
+    pub fn new(albedo : Vec3, fuzz : f32) -> Self {
+        Metal{ albedo : SolidColor::shared(albedo), fuzz }
+    }
+}
+
 impl Material for Metal {
-    fn scatter(&self, r_in : &Ray, rec : &HitRecord, attenuation : &mut Vec3, scattered : &mut Ray) -> bool{
+    // the fuzzed reflection has no pdf we can evaluate, so it's treated as specular
+    fn scatter(&self, r_in : &Ray, rec : &HitRecord, sampler : &mut dyn Sampler) -> Option<ScatterRecord> {
         let reflected = Vec3::reflect( Vec3::normalize( r_in.dir),  rec.normal );
-        *scattered = Ray::new(rec.p, reflected + self.fuzz * Vec3::random_in_unit_sphere() );
-        *attenuation = self.albedo;
-        return true;
+        Some(ScatterRecord {
+            direction : reflected + self.fuzz * Vec3::random_in_unit_sphere(sampler.rng()),
+            value : self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf : 1.0,
+            is_specular : true,
+        })
+    }
+
+    fn albedo(&self, rec : &HitRecord) -> Vec3 {
+        self.albedo.value(rec.u, rec.v, &rec.p)
     }
 }
 
@@ -51,40 +132,66 @@ impl Dieletric {
     fn reflectance( cosine : f32, ref_idx : f32 )  -> f32 {
         let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
         r0 = r0 * r0;
-        return r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0);
+        r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
     }
 }
 
 impl Material for Dieletric {
 
 
-    fn scatter(&self, r_in : &Ray, rec : &HitRecord, attenuation : &mut Vec3, scattered : &mut Ray) -> bool{
+    fn scatter(&self, r_in : &Ray, rec : &HitRecord, sampler : &mut dyn Sampler) -> Option<ScatterRecord> {
         
         let refraction_ratio = if rec.front_face { 1.0/self.ir } else { self.ir };
 
         let unit_direction = Vec3::normalize(r_in.dir);
 
-        let cos_theta = Vec3::dot( &(unit_direction * -1.0), &rec.normal ).min(1.0);
+        let cos_theta = Vec3::dot( &-unit_direction, &rec.normal ).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract  = refraction_ratio * sin_theta > 1.0;
         
-        let mut rng = rand::thread_rng();
-        
         let direction = 
-            if cannot_refract || Dieletric::reflectance(cos_theta, refraction_ratio) > rng.gen::<f32>() { 
+            if cannot_refract || Dieletric::reflectance(cos_theta, refraction_ratio) > sampler.get_1d() { 
                 Vec3::reflect(unit_direction, rec.normal) } 
              else {
                 Vec3::refract(unit_direction, rec.normal, refraction_ratio)
             };
         
-        *attenuation = Vec3::one();
-        *scattered = Ray::new(rec.p, direction);
-        return true;
+        Some(ScatterRecord {
+            direction,
+            value : Vec3::one(),
+            pdf : 1.0,
+            is_specular : true,
+        })
     }
 }
 
 
+// Emits light from both sides of the surface and doesn't reflect anything.
+#[derive(Clone)]
+pub struct DiffuseLight {
+    pub emit : Vec3,
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in : &Ray, _rec : &HitRecord, _sampler : &mut dyn Sampler) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, _rec : &HitRecord) -> Vec3 {
+        self.emit
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    fn albedo(&self, _rec : &HitRecord) -> Vec3 {
+        let e = self.emit;
+        Vec3::new(e.x.min(1.0), e.y.min(1.0), e.z.min(1.0))
+    }
+}
+
 
 // Trait impl
 pub trait  MaterialClone {
@@ -104,4 +211,54 @@ impl Clone for Box<dyn Material> {
     fn clone(&self) -> Box<dyn Material> {
         self.clone_box()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::sampler::SamplerKind;
+
+    #[test]
+    fn scatter_record(){
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 1.0, 0.0);
+        let r_in = Ray::new(Vec3::new(0.0, 1.0, -1.0), Vec3::new(0.0, -1.0, 1.0));
+        let wo = -Vec3::normalize(r_in.dir);
+        let mut sampler = SamplerKind::Sobol.build(0, 100);
+
+        let albedo = Vec3::new(0.5, 0.25, 1.0);
+        let lambertian = Lambertian::new(albedo);
+        for i in 0..100 {
+            sampler.start_sample(0, 0, i);
+            let srec = lambertian.scatter(&r_in, &rec, sampler.as_mut()).unwrap();
+            assert!(!srec.is_specular);
+            assert!((srec.pdf - lambertian.pdf(&rec, &srec.direction, &wo)).abs() < 1e-6);
+
+            // cosine sampling cancels out, leaving the albedo
+            let weight = srec.value / srec.pdf;
+            assert!((weight - albedo).length() < 1e-4);
+        }
+        assert_eq!(lambertian.pdf(&rec, &Vec3::new(0.0, -1.0, 0.0), &wo), 0.0);
+
+        let glass = Dieletric{ ir : 1.5 };
+        let srec = glass.scatter(&r_in, &rec, sampler.as_mut()).unwrap();
+        assert!(srec.is_specular);
+        assert_eq!(glass.eval(&rec, &srec.direction, &wo), Vec3::zero());
+
+        assert!(DiffuseLight{ emit : Vec3::one() }.scatter(&r_in, &rec, sampler.as_mut()).is_none());
+    }
+
+    #[test]
+    fn diffuse_light(){
+        let rec = HitRecord::new();
+        let light = DiffuseLight{ emit : Vec3::new(4.0, 2.0, 0.5) };
+        assert_eq!(light.emitted(&rec), Vec3::new(4.0, 2.0, 0.5));
+        assert!(light.is_emissive());
+        // clamped so the albedo AOV stays in [0, 1]
+        assert_eq!(light.albedo(&rec), Vec3::new(1.0, 1.0, 0.5));
+
+        let lambertian = Lambertian::new(Vec3::one());
+        assert_eq!(lambertian.emitted(&rec), Vec3::zero());
+        assert!(!lambertian.is_emissive());
+    }
+}
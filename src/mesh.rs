@@ -0,0 +1,208 @@
+use std::sync::Arc;
+
+use crate::vec::Vec3;
+use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::bvh::BvhNode;
+use crate::materials::Material;
+use crate::hitrecord::{HitRecord, Hittable};
+use crate::geometry::{Triangle, intersect_triangle, set_triangle_hit};
+
+#[derive(Debug, PartialEq)]
+pub enum MeshError {
+    IndexCount(usize),
+    IndexOutOfRange(u32),
+    NormalCount(usize),
+    TexcoordCount(usize),
+}
+
+impl std::fmt::Display for MeshError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MeshError::IndexCount(n) => write!(f, "mesh index count must be a multiple of 3, found {}", n),
+            MeshError::IndexOutOfRange(i) => write!(f, "mesh index {} is out of range", i),
+            MeshError::NormalCount(n) => write!(f, "mesh needs one normal per vertex, found {}", n),
+            MeshError::TexcoordCount(n) => write!(f, "mesh needs one texcoord per vertex, found {}", n),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+// Vertex and index buffers shared by every triangle of a mesh, normals and texcoords are per vertex.
+pub struct MeshData {
+    pub positions : Vec<Vec3>,
+    pub normals : Option<Vec<Vec3>>,
+    pub texcoords : Option<Vec<[f32; 2]>>,
+    pub indices : Vec<u32>,
+    pub material : Box<dyn Material + Send + Sync>,
+}
+
+impl MeshData {
+    fn triangle_indices(&self, triangle : usize) -> [usize; 3] {
+        [
+            self.indices[triangle * 3] as usize,
+            self.indices[triangle * 3 + 1] as usize,
+            self.indices[triangle * 3 + 2] as usize,
+        ]
+    }
+
+    fn triangle_positions(&self, triangle : usize) -> [Vec3; 3] {
+        let [i0, i1, i2] = self.triangle_indices(triangle);
+        [self.positions[i0], self.positions[i1], self.positions[i2]]
+    }
+
+    fn triangle_normals(&self, triangle : usize) -> Option<[Vec3; 3]> {
+        let [i0, i1, i2] = self.triangle_indices(triangle);
+        self.normals.as_ref().map(|n| [n[i0], n[i1], n[i2]])
+    }
+
+    fn triangle_texcoords(&self, triangle : usize) -> Option<[[f32; 2]; 3]> {
+        let [i0, i1, i2] = self.triangle_indices(triangle);
+        self.texcoords.as_ref().map(|uv| [uv[i0], uv[i1], uv[i2]])
+    }
+}
+
+// a single triangle of a mesh, only holds a reference to the shared buffers.
+struct MeshTriangle {
+    mesh : Arc<MeshData>,
+    index : usize,
+}
+
+impl Hittable for MeshTriangle {
+
+    fn hit(&self, r : &Ray, t_min : f32, t_max : f32, hit_record : &mut HitRecord ) -> bool{
+        let positions = self.mesh.triangle_positions(self.index);
+        let [p0, p1, p2] = positions;
+
+        match intersect_triangle(r, p0, p1, p2, t_min, t_max) {
+            Some((t, barycentric)) => {
+                let normals = self.mesh.triangle_normals(self.index);
+                let texcoords = self.mesh.triangle_texcoords(self.index);
+                set_triangle_hit(r, t, positions, barycentric, normals, texcoords, hit_record);
+                hit_record.material = Some(self.mesh.material.clone_box());
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points(&self.mesh.triangle_positions(self.index)).pad(1e-4)
+    }
+}
+
+// Indexed triangle mesh, every 3 entries in `indices` make a triangle. The triangles are kept in
+// their own BVH so the whole mesh shows up as a single object in the scene.
+pub struct TriangleMesh {
+    mesh : Arc<MeshData>,
+    triangles : BvhNode,
+}
+
+impl TriangleMesh {
+    pub fn new(data : MeshData) -> Result<Self, MeshError> {
+        if data.indices.len() % 3 != 0 {
+            return Err(MeshError::IndexCount(data.indices.len()));
+        }
+        if let Some(i) = data.indices.iter().find(|i| (**i as usize) >= data.positions.len()) {
+            return Err(MeshError::IndexOutOfRange(*i));
+        }
+        match &data.normals {
+            Some(n) if n.len() != data.positions.len() => return Err(MeshError::NormalCount(n.len())),
+            _ => {},
+        }
+        match &data.texcoords {
+            Some(uv) if uv.len() != data.positions.len() => return Err(MeshError::TexcoordCount(uv.len())),
+            _ => {},
+        }
+
+        let mesh = Arc::new(data);
+
+        let triangles : Vec<Box<dyn Hittable + Send + Sync>> = (0..mesh.indices.len() / 3)
+            .map(|index| Box::new(MeshTriangle { mesh : mesh.clone(), index }) as Box<dyn Hittable + Send + Sync>)
+            .collect();
+
+        Ok(TriangleMesh {
+            mesh,
+            triangles : BvhNode::new(triangles),
+        })
+    }
+}
+
+impl Hittable for TriangleMesh {
+
+    fn hit(&self, r : &Ray, t_min : f32, t_max : f32, hit_record : &mut HitRecord ) -> bool{
+        self.triangles.hit(r, t_min, t_max, hit_record)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.triangles.bounding_box()
+    }
+
+    // an emissive mesh turns into one light per triangle
+    fn lights(&self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        if !self.mesh.material.is_emissive() {
+            return Vec::new();
+        }
+
+        (0..self.mesh.indices.len() / 3).map(|i| {
+            let triangle = Triangle {
+                vertices : self.mesh.triangle_positions(i),
+                normals : self.mesh.triangle_normals(i),
+                texcoords : self.mesh.triangle_texcoords(i),
+                material : self.mesh.material.clone_box(),
+            };
+            Box::new(triangle) as Box<dyn Hittable + Send + Sync>
+        }).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::materials::Lambertian;
+
+    #[test]
+    fn mesh_hit(){
+        // unit cube, made of 12 triangles
+        let positions = vec![
+            Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 1.0), Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 1.0, 1.0),
+        ];
+        let indices = vec![
+            0, 2, 1, 0, 3, 2,
+            4, 5, 6, 4, 6, 7,
+            0, 1, 5, 0, 5, 4,
+            3, 6, 2, 3, 7, 6,
+            0, 4, 7, 0, 7, 3,
+            1, 2, 6, 1, 6, 5,
+        ];
+        let cube = |indices : Vec<u32>, normals : Option<Vec<Vec3>>| TriangleMesh::new(MeshData {
+            positions : positions.clone(),
+            normals,
+            texcoords : None,
+            indices,
+            material : Box::new( Lambertian::new(Vec3::one()) ),
+        });
+        // bad buffers are errors, not panics
+        assert_eq!(cube(vec![0, 1], None).err(), Some(MeshError::IndexCount(2)));
+        assert_eq!(cube(vec![0, 1, 8], None).err(), Some(MeshError::IndexOutOfRange(8)));
+        assert_eq!(cube(vec![0, 1, 2], Some(vec![Vec3::one()])).err(), Some(MeshError::NormalCount(1)));
+        let mesh = cube(indices, None).unwrap();
+
+        let mut rec = HitRecord::new();
+        let r = Ray::new(Vec3::new(0.5, 0.5, -3.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(mesh.hit(&r, 0.001, f32::INFINITY, &mut rec));
+        assert_eq!(rec.t, 3.0);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, -1.0));
+
+        let inside = Ray::new(Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.0, 1.0, 0.0));
+        assert!(mesh.hit(&inside, 0.001, f32::INFINITY, &mut rec));
+        assert_eq!(rec.t, 0.5);
+        assert!(!rec.front_face);
+
+        let miss = Ray::new(Vec3::new(2.0, 0.5, -3.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(!mesh.hit(&miss, 0.001, f32::INFINITY, &mut rec));
+    }
+}
@@ -0,0 +1,198 @@
+use rand::Rng;
+use rand_pcg::Pcg32;
+
+use crate::vec::Vec3;
+use crate::rng;
+
+const POINT_COUNT : usize = 256;
+
+// Gradient noise from "Ray Tracing: The Next Week", random unit vectors at the lattice points
+// blended with a hermite curve. The tables come from `seed`, so the same seed gives the same noise.
+pub struct Perlin {
+    ranvec : Vec<Vec3>,
+    perm_x : Vec<usize>,
+    perm_y : Vec<usize>,
+    perm_z : Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed : u64) -> Self {
+        let mut rng = rng::seeded(seed);
+
+        let ranvec = (0..POINT_COUNT).map(|_| {
+            let z : f32 = rng.gen_range(-1.0..1.0);
+            let phi = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+            let r = (1.0 - z * z).sqrt();
+            Vec3::new(r * phi.cos(), r * phi.sin(), z)
+        }).collect();
+
+        Perlin {
+            ranvec,
+            perm_x : Perlin::generate_perm(&mut rng),
+            perm_y : Perlin::generate_perm(&mut rng),
+            perm_z : Perlin::generate_perm(&mut rng),
+        }
+    }
+
+    fn generate_perm(rng : &mut Pcg32) -> Vec<usize> {
+        let mut p : Vec<usize> = (0..POINT_COUNT).collect();
+        for i in (1..POINT_COUNT).rev() {
+            let target = rng.gen_range(0..=i);
+            p.swap(i, target);
+        }
+        p
+    }
+
+    // between -1 and 1, 0 at every lattice point
+    pub fn noise(&self, p : &Vec3) -> f32 {
+        let u = p.x - p.x.floor();
+        let v = p.y - p.y.floor();
+        let w = p.z - p.z.floor();
+
+        let i = p.x.floor() as i64;
+        let j = p.y.floor() as i64;
+        let k = p.z.floor() as i64;
+
+        let mut c = [[[Vec3::zero(); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    let index = self.perm_x[((i + di as i64) & 255) as usize]
+                              ^ self.perm_y[((j + dj as i64) & 255) as usize]
+                              ^ self.perm_z[((k + dk as i64) & 255) as usize];
+                    *corner = self.ranvec[index];
+                }
+            }
+        }
+
+        Perlin::interpolate(&c, u, v, w)
+    }
+
+    fn interpolate(c : &[[[Vec3; 2]; 2]; 2], u : f32, v : f32, w : f32) -> f32 {
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+
+        let mut accum = 0.0;
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, corner) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f32, j as f32, k as f32);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                           * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                           * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                           * Vec3::dot(corner, &weight);
+                }
+            }
+        }
+        accum
+    }
+
+    // sum of `depth` octaves of noise, each one twice the frequency and half the weight of the last
+    pub fn turbulence(&self, p : &Vec3, depth : u32) -> f32 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_p);
+            weight *= 0.5;
+            temp_p = temp_p * 2.0;
+        }
+        accum.abs()
+    }
+}
+
+// Cellular noise (Worley 1996): one feature point in every unit cube, placed by hashing the cube
+// coordinates with the seed, and the noise is the distance to the closest one.
+pub struct Worley {
+    seed : u64,
+}
+
+impl Worley {
+    pub fn new(seed : u64) -> Self {
+        Worley { seed }
+    }
+
+    fn feature_point(&self, i : i64, j : i64, k : i64) -> Vec3 {
+        let mut h = rng::hash([self.seed, i as u64, j as u64, k as u64]);
+        let mut next = || {
+            h = rng::hash([h]);
+            (h >> 40) as f32 / (1u64 << 24) as f32
+        };
+        Vec3::new(i as f32 + next(), j as f32 + next(), k as f32 + next())
+    }
+
+    // distance to the closest feature point, between 0 and sqrt(3)
+    pub fn distance(&self, p : &Vec3) -> f32 {
+        let i = p.x.floor() as i64;
+        let j = p.y.floor() as i64;
+        let k = p.z.floor() as i64;
+
+        let mut closest = f32::INFINITY;
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let d = (self.feature_point(i + di, j + dj, k + dk) - *p).length_squared();
+                    closest = closest.min(d);
+                }
+            }
+        }
+        closest.sqrt()
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn points() -> impl Iterator<Item = Vec3> {
+        (0..500).map(|i| {
+            let a = i as f32 * 0.618;
+            Vec3::new(a.sin() * 13.7, a.cos() * 5.3 - 2.0, a * 0.21)
+        })
+    }
+
+    #[test]
+    fn perlin_noise(){
+        let perlin = Perlin::new(7);
+        let same = Perlin::new(7);
+        let other = Perlin::new(8);
+
+        let mut differs = false;
+        for p in points() {
+            let n = perlin.noise(&p);
+            assert!((-1.0..=1.0).contains(&n));
+            assert_eq!(n, same.noise(&p));
+            differs |= n != other.noise(&p);
+        }
+        assert!(differs);
+
+        assert_eq!(perlin.noise(&Vec3::new(3.0, -2.0, 5.0)), 0.0);
+        assert!(perlin.turbulence(&Vec3::new(0.3, 0.7, 0.1), 7) >= 0.0);
+    }
+
+    #[test]
+    fn worley_noise(){
+        let worley = Worley::new(7);
+        let other = Worley::new(8);
+
+        let mut differs = false;
+        for p in points() {
+            let d = worley.distance(&p);
+            assert!((0.0..=3f32.sqrt()).contains(&d));
+            assert_eq!(d, Worley::new(7).distance(&p));
+            differs |= d != other.distance(&p);
+
+            // small steps only change the distance a little
+            let step = Vec3::new(1e-3, 0.0, 0.0);
+            assert!((worley.distance(&(p + step)) - d).abs() <= 1.01e-3);
+        }
+        assert!(differs);
+
+        let feature = worley.feature_point(2, -3, 4);
+        assert!(worley.distance(&feature) < 1e-6);
+    }
+}
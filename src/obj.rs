@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::vec::Vec3;
+use crate::hitrecord::Hittable;
+use crate::materials::{Material, Lambertian, Metal, Dieletric};
+use crate::mesh::{MeshData, MeshError, TriangleMesh};
+use crate::texture::ImageTexture;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, std::io::Error),
+    Parse { path : PathBuf, line : usize, message : String },
+    Image(PathBuf, image::ImageError),
+    Mesh(PathBuf, MeshError),
+}
+
+impl std::fmt::Display for ObjError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ObjError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            ObjError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Image(path, err) => write!(f, "{}: {}", path.display(), err),
+            ObjError::Mesh(path, err) => write!(f, "{}: {}", path.display(), err),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+// Material description as read from a .mtl file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtlMaterial {
+    pub diffuse : Vec3,    // Kd
+    pub specular : Vec3,   // Ks
+    pub shininess : f32,   // Ns
+    pub ior : f32,         // Ni
+    pub illum : u32,
+    pub diffuse_map : Option<PathBuf>, // map_Kd, resolved from the directory of the .mtl file
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse : Vec3::new(0.8, 0.8, 0.8),
+            specular : Vec3::zero(),
+            shininess : 0.0,
+            ior : 1.5,
+            illum : 2,
+            diffuse_map : None,
+        }
+    }
+}
+
+impl MtlMaterial {
+
+    // Maps the MTL parameters onto the materials the renderer has:
+    // - the refracting illum models (4, 6, 7 and 9) become Dieletric with Ni as the index of refraction,
+    //   a d or Tr alone doesn't, a slightly see-through or cut-out surface isn't glass
+    // - reflective surfaces (illum 3 or 5, or a black diffuse with some specular) become Metal tinted by Ks,
+    //   with the fuzz derived from the Ns exponent
+    // - everything else is Lambertian with Kd as albedo, or the map_Kd image when there is one
+    pub fn to_material(&self) -> Result<Box<dyn Material + Send + Sync>, ObjError> {
+        let transparent = matches!(self.illum, 4 | 6 | 7 | 9);
+        let reflective = matches!(self.illum, 3 | 5) || (max_component(self.diffuse) == 0.0 && max_component(self.specular) > 0.0);
+
+        Ok(if transparent {
+            Box::new( Dieletric{ ir : self.ior } )
+        } else if reflective {
+            let fuzz = (2.0 / (self.shininess + 2.0)).sqrt().min(1.0);
+            Box::new( Metal::new(self.specular, fuzz) )
+        } else if let Some(map) = &self.diffuse_map {
+            let texture = ImageTexture::load(map).map_err(|e| ObjError::Image(map.clone(), e))?;
+            Box::new( Lambertian{ albedo : Arc::new(texture) } )
+        } else {
+            Box::new( Lambertian::new(self.diffuse) )
+        })
+    }
+}
+
+fn max_component(v : Vec3) -> f32 {
+    v.x.max(v.y).max(v.z)
+}
+
+fn read_file(path : &Path) -> Result<String, ObjError> {
+    std::fs::read_to_string(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))
+}
+
+struct LineParser<'a> {
+    path : &'a Path,
+    line : usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn error(&self, message : String) -> ObjError {
+        ObjError::Parse { path : self.path.to_path_buf(), line : self.line, message }
+    }
+
+    fn floats(&self, args : &[&str], min : usize, max : usize) -> Result<Vec<f32>, ObjError> {
+        if args.len() < min || args.len() > max {
+            return Err(self.error(format!("expected {} to {} numbers, found {}", min, max, args.len())));
+        }
+        args.iter()
+            .map(|a| a.parse::<f32>().map_err(|_| self.error(format!("invalid number `{}`", a))))
+            .collect()
+    }
+
+    fn vec3(&self, args : &[&str]) -> Result<Vec3, ObjError> {
+        let v = self.floats(args, 3, 4)?;
+        Ok(Vec3::new(v[0], v[1], v[2]))
+    }
+
+    // OBJ indices start at 1, negative ones are relative to the end of the list
+    fn index(&self, token : &str, count : usize, kind : &str) -> Result<usize, ObjError> {
+        let i : i64 = token.parse().map_err(|_| self.error(format!("invalid {} index `{}`", kind, token)))?;
+        let resolved = if i > 0 { i - 1 } else { count as i64 + i };
+
+        if i == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(self.error(format!("{} index {} out of range, {} defined so far", kind, i, count)));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+pub fn parse_mtl(source : &str, path : &Path) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current : Option<(String, MtlMaterial)> = None;
+
+    for (line_index, line) in source.lines().enumerate() {
+        let parser = LineParser { path, line : line_index + 1 };
+        let tokens : Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+
+        if tokens[0] == "newmtl" {
+            if let Some((name, mat)) = current.take() {
+                materials.insert(name, mat);
+            }
+            current = Some((tokens[1..].join(" "), MtlMaterial::default()));
+            continue;
+        }
+
+        let mat = match current.as_mut() {
+            Some((_, mat)) => mat,
+            None => return Err(parser.error(format!("`{}` before any newmtl", tokens[0]))),
+        };
+        let args = &tokens[1..];
+
+        match tokens[0] {
+            "Kd" => mat.diffuse = parser.vec3(args)?,
+            "Ks" => mat.specular = parser.vec3(args)?,
+            "Ns" => mat.shininess = parser.floats(args, 1, 1)?[0],
+            "Ni" => mat.ior = parser.floats(args, 1, 1)?[0],
+            // opacity, for blending and cut-outs rather than refraction, so it's checked but not used
+            "d" | "Tr" => { parser.floats(args, 1, 1)?; },
+            "illum" => mat.illum = parser.floats(args, 1, 1)?[0] as u32,
+            // options like -s or -o come before the file name, and are ignored
+            "map_Kd" => match args.last() {
+                Some(file) => mat.diffuse_map = Some(path.parent().unwrap_or_else(|| Path::new(".")).join(file)),
+                None => return Err(parser.error("map_Kd needs a file name".to_string())),
+            },
+            // other texture maps, emission, etc. are not supported yet
+            _ => {},
+        }
+    }
+
+    if let Some((name, mat)) = current.take() {
+        materials.insert(name, mat);
+    }
+    Ok(materials)
+}
+
+// triangles collected for one (group, material) pair, vertices are de-duplicated per mesh
+#[derive(Default)]
+struct MeshBuilder {
+    material : Option<String>,
+    vertex_map : HashMap<(usize, Option<usize>, Option<usize>), u32>,
+    vertices : Vec<(usize, Option<usize>, Option<usize>)>,
+    indices : Vec<u32>,
+}
+
+impl MeshBuilder {
+    fn add_vertex(&mut self, key : (usize, Option<usize>, Option<usize>)) -> u32 {
+        let vertices = &mut self.vertices;
+        *self.vertex_map.entry(key).or_insert_with(|| {
+            vertices.push(key);
+            (vertices.len() - 1) as u32
+        })
+    }
+
+    fn build(self, positions : &[Vec3], texcoords : &[[f32; 2]], normals : &[Vec3], material : Box<dyn Material + Send + Sync>) -> Result<TriangleMesh, MeshError> {
+        // attributes are only kept if every vertex of the mesh has them
+        let mesh_normals = if self.vertices.iter().all(|v| v.2.is_some()) {
+            Some(self.vertices.iter().map(|v| normals[v.2.unwrap()]).collect())
+        } else {
+            None
+        };
+        let mesh_texcoords = if self.vertices.iter().all(|v| v.1.is_some()) {
+            Some(self.vertices.iter().map(|v| texcoords[v.1.unwrap()]).collect())
+        } else {
+            None
+        };
+
+        TriangleMesh::new(MeshData {
+            positions : self.vertices.iter().map(|v| positions[v.0]).collect(),
+            normals : mesh_normals,
+            texcoords : mesh_texcoords,
+            indices : self.indices,
+            material,
+        })
+    }
+}
+
+// Parses a Wavefront OBJ file, `base_dir` is where referenced .mtl files are looked up.
+// Every group / material combination becomes its own TriangleMesh, polygons are triangulated as fans.
+pub fn parse_obj(source : &str, path : &Path, base_dir : &Path) -> Result<Vec<Box<dyn Hittable + Send + Sync>>, ObjError> {
+    let mut positions : Vec<Vec3> = Vec::new();
+    let mut texcoords : Vec<[f32; 2]> = Vec::new();
+    let mut normals : Vec<Vec3> = Vec::new();
+    let mut materials : HashMap<String, MtlMaterial> = HashMap::new();
+
+    let mut finished : Vec<MeshBuilder> = Vec::new();
+    let mut current = MeshBuilder::default();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let parser = LineParser { path, line : line_index + 1 };
+        let tokens : Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+        let args = &tokens[1..];
+
+        match tokens[0] {
+            "v" => positions.push(parser.vec3(args)?),
+            "vn" => {
+                let n = parser.vec3(args)?;
+                if n.length_squared() == 0.0 {
+                    return Err(parser.error("normal has zero length".to_string()));
+                }
+                normals.push(Vec3::normalize(n));
+            },
+            "vt" => {
+                let uv = parser.floats(args, 1, 3)?;
+                texcoords.push([uv[0], *uv.get(1).unwrap_or(&0.0)]);
+            },
+            "f" => {
+                if args.len() < 3 {
+                    return Err(parser.error(format!("face needs at least 3 vertices, found {}", args.len())));
+                }
+
+                let mut face = Vec::with_capacity(args.len());
+                for vertex in args {
+                    let mut parts = vertex.split('/');
+                    let p = parser.index(parts.next().unwrap_or(""), positions.len(), "position")?;
+                    let t = match parts.next() {
+                        Some(t) if !t.is_empty() => Some(parser.index(t, texcoords.len(), "texcoord")?),
+                        _ => None,
+                    };
+                    let n = match parts.next() {
+                        Some(n) if !n.is_empty() => Some(parser.index(n, normals.len(), "normal")?),
+                        _ => None,
+                    };
+                    face.push(current.add_vertex((p, t, n)));
+                }
+
+                for i in 1..face.len() - 1 {
+                    current.indices.extend_from_slice(&[face[0], face[i], face[i + 1]]);
+                }
+            },
+            "g" | "o" | "usemtl" => {
+                let material = if tokens[0] == "usemtl" {
+                    let name = args.join(" ");
+                    if !materials.contains_key(&name) {
+                        return Err(parser.error(format!("material `{}` isn't in any mtllib", name)));
+                    }
+                    Some(name)
+                } else {
+                    current.material.clone()
+                };
+
+                let previous = std::mem::replace(&mut current, MeshBuilder { material, ..MeshBuilder::default() });
+                if !previous.indices.is_empty() {
+                    finished.push(previous);
+                }
+            },
+            "mtllib" => {
+                for file in args {
+                    let mtl_path = base_dir.join(file);
+                    materials.extend(parse_mtl(&read_file(&mtl_path)?, &mtl_path)?);
+                }
+            },
+            // smoothing groups, lines, points and curves are ignored
+            _ => {},
+        }
+    }
+
+    if !current.indices.is_empty() {
+        finished.push(current);
+    }
+
+    // materials are only built once, so meshes using the same one share its textures
+    let mut built : HashMap<Option<String>, Box<dyn Material + Send + Sync>> = HashMap::new();
+    let mut objects : Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
+    for mesh in finished {
+        let material = match built.get(&mesh.material) {
+            Some(m) => m.clone_box(),
+            None => {
+                let mtl = mesh.material.as_ref().and_then(|name| materials.get(name)).cloned().unwrap_or_default();
+                let m = mtl.to_material()?;
+                built.insert(mesh.material.clone(), m.clone_box());
+                m
+            },
+        };
+        let mesh = mesh.build(&positions, &texcoords, &normals, material).map_err(|e| ObjError::Mesh(path.to_path_buf(), e))?;
+        objects.push( Box::new(mesh) );
+    }
+    Ok(objects)
+}
+
+pub fn load_obj(path : &Path) -> Result<Vec<Box<dyn Hittable + Send + Sync>>, ObjError> {
+    let source = read_file(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    parse_obj(&source, path, base_dir)
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::ray::Ray;
+    use crate::hitrecord::HitRecord;
+    use crate::sampler::SamplerKind;
+
+    #[test]
+    fn obj_quads_and_groups(){
+        let source = "
+            # two unit quads, one per group
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vn 0 0 1
+            vt 0 0
+            g first
+            f 1/1/1 2/1/1 3/1/1 4/1/1
+            g second
+            f -4//1 -3//1 -2//1 -1//1
+        ";
+        let objects = parse_obj(source, Path::new("test.obj"), Path::new(".")).unwrap();
+        assert_eq!(objects.len(), 2);
+
+        let r = Ray::new(Vec3::new(0.9, 0.9, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        for obj in &objects {
+            let mut rec = HitRecord::new();
+            assert!(obj.hit(&r, 0.001, f32::INFINITY, &mut rec));
+            assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn obj_errors(){
+        let bad_index = parse_obj("v 0 0 0\nv 1 0 0\nf 1 2 3\n", Path::new("bad.obj"), Path::new("."));
+        assert_eq!(bad_index.err().unwrap().to_string(), "bad.obj:3: position index 3 out of range, 2 defined so far");
+
+        let bad_number = parse_obj("v 0 zero 0\n", Path::new("bad.obj"), Path::new("."));
+        assert_eq!(bad_number.err().unwrap().to_string(), "bad.obj:1: invalid number `zero`");
+
+        let zero_normal = parse_obj("v 0 0 0\nvn 0 0 0\n", Path::new("bad.obj"), Path::new("."));
+        assert_eq!(zero_normal.err().unwrap().to_string(), "bad.obj:2: normal has zero length");
+
+        let unknown = parse_obj("v 0 0 0\nusemtl chrome\n", Path::new("bad.obj"), Path::new("."));
+        assert_eq!(unknown.err().unwrap().to_string(), "bad.obj:2: material `chrome` isn't in any mtllib");
+    }
+
+    #[test]
+    fn mtl_parse(){
+        let source = "
+            newmtl glass
+            Kd 0 0 0
+            Ni 1.33
+            d 0.1
+            illum 7
+            newmtl red plastic
+            Kd 0.8 0.1 0.1
+            Ks 0.5 0.5 0.5
+            Ns 100
+            map_Kd -s 2 2 1 textures/red.png
+        ";
+        let materials = parse_mtl(source, Path::new("test.mtl")).unwrap();
+        assert_eq!(materials.len(), 2);
+
+        let glass = &materials["glass"];
+        assert_eq!(glass.ior, 1.33);
+        assert_eq!(glass.illum, 7);
+
+        let red = &materials["red plastic"];
+        assert_eq!(red.diffuse, Vec3::new(0.8, 0.1, 0.1));
+        assert_eq!(red.shininess, 100.0);
+        assert_eq!(red.illum, 2);
+        assert_eq!(red.diffuse_map, Some(PathBuf::from("textures/red.png")));
+
+        let missing = red.to_material();
+        assert!(missing.err().unwrap().to_string().starts_with("textures/red.png: "));
+    }
+
+    #[test]
+    fn mtl_to_material(){
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 1.0, 0.0);
+        rec.front_face = true;
+        let r_in = Ray::new(Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+        let mut sampler = SamplerKind::Independent.build(0, 1);
+        sampler.start_sample(0, 0, 0);
+
+        // Kd only, a diffuse surface
+        let plastic = MtlMaterial { diffuse : Vec3::new(0.8, 0.1, 0.1), ..MtlMaterial::default() }.to_material().unwrap();
+        assert!(!plastic.scatter(&r_in, &rec, sampler.as_mut()).unwrap().is_specular);
+        assert_eq!(plastic.albedo(&rec), Vec3::new(0.8, 0.1, 0.1));
+
+        // illum 3, a mirror tinted by Ks
+        let ks = Vec3::new(0.9, 0.8, 0.7);
+        let mirror = MtlMaterial { specular : ks, shininess : 1e6, illum : 3, ..MtlMaterial::default() }.to_material().unwrap();
+        let srec = mirror.scatter(&r_in, &rec, sampler.as_mut()).unwrap();
+        assert!(srec.is_specular);
+        assert_eq!(srec.value, ks);
+        assert!((Vec3::normalize(srec.direction) - Vec3::normalize(Vec3::new(1.0, 1.0, 0.0))).length() < 0.01);
+
+        // a nearly opaque d stays diffuse
+        let tinted = MtlMaterial { illum : 2, ..MtlMaterial::default() };
+        let source = "newmtl tinted\nKd 0.8 0.8 0.8\nd 0.99\nillum 2\n";
+        assert_eq!(parse_mtl(source, Path::new("test.mtl")).unwrap()["tinted"], tinted);
+        assert!(!tinted.to_material().unwrap().scatter(&r_in, &rec, sampler.as_mut()).unwrap().is_specular);
+
+        // illum 7, glass that lets most of the light through
+        let glass = MtlMaterial { illum : 7, ior : 1.5, ..MtlMaterial::default() }.to_material().unwrap();
+        let refracted = (0..100).filter(|i| {
+            sampler.start_sample(0, 0, *i);
+            let srec = glass.scatter(&r_in, &rec, sampler.as_mut()).unwrap();
+            assert!(srec.is_specular && srec.value == Vec3::one());
+            srec.direction.y < 0.0
+        }).count();
+        assert!(refracted > 50);
+    }
+}
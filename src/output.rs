@@ -0,0 +1,436 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::color::Color;
+use crate::film::Film;
+use crate::tonemap::{self, ToneMapping};
+
+#[derive(Debug, Copy, Clone, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Tga,
+    // linear float formats, written without any quantization
+    Exr,
+    Hdr,
+    Pfm,
+}
+
+impl OutputFormat {
+    pub fn from_path(path : &Path) -> Option<OutputFormat> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "bmp" => Some(OutputFormat::Bmp),
+            "tga" => Some(OutputFormat::Tga),
+            "exr" => Some(OutputFormat::Exr),
+            "hdr" => Some(OutputFormat::Hdr),
+            "pfm" => Some(OutputFormat::Pfm),
+            _ => None,
+        }
+    }
+}
+
+// how the samples of an OpenEXR file are stored
+#[derive(Debug, Copy, Clone, PartialEq, ValueEnum)]
+pub enum ExrPrecision {
+    // 16 bit floats, half the size and plenty for colours
+    Half,
+    // 32 bit floats
+    Float,
+}
+
+// Extra per pixel data that can be written next to the colour, see film::Aovs
+#[derive(Debug, Copy, Clone, PartialEq, ValueEnum)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Position,
+    Depth,
+    ObjectId,
+    Samples,
+}
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Depth => "depth",
+            Aov::ObjectId => "object_id",
+            Aov::Samples => "samples",
+        }
+    }
+
+    // names of the channels in an EXR layer, single value AOVs only use the first one of `values`
+    fn channel_names(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::ObjectId => &["id"],
+            Aov::Samples => &["count"],
+        }
+    }
+
+    // the raw values, single value AOVs are repeated in the 3 channels
+    fn values(&self, film : &Film) -> Vec<Color<f32>> {
+        film.aovs().iter().map(|a| {
+            let gray = |v : f32| Color::new(v, v, v);
+            match self {
+                Aov::Albedo => Color::new(a.albedo.x, a.albedo.y, a.albedo.z),
+                Aov::Normal => Color::new(a.normal.x, a.normal.y, a.normal.z),
+                Aov::Position => Color::new(a.position.x, a.position.y, a.position.z),
+                Aov::Depth => gray(a.depth),
+                Aov::ObjectId => gray(a.object_id as f32),
+                Aov::Samples => gray(a.samples as f32),
+            }
+        }).collect()
+    }
+
+    fn channels(&self, film : &Film) -> Vec<Channel> {
+        let values = self.values(film);
+        self.channel_names().iter().enumerate().map(|(i, suffix)| Channel {
+            name : format!("{}.{}", self.name(), suffix),
+            data : values.iter().map(|c| [c.r, c.g, c.b][i]).collect(),
+        }).collect()
+    }
+
+    // something viewable for 8 bit formats, between 0 and 1 and already encoded
+    fn preview(&self, film : &Film) -> Vec<Color<f32>> {
+        let values = self.values(film);
+        // depth and sample counts are divided by the largest one in the image
+        let max = values.iter().map(|c| c.r).filter(|v| v.is_finite()).fold(0.0f32, f32::max).max(1e-6);
+
+        film.aovs().iter().zip(values).map(|(a, c)| match self {
+            Aov::Albedo => Color::new(tonemap::srgb_oetf(c.r.clamp(0.0, 1.0)), tonemap::srgb_oetf(c.g.clamp(0.0, 1.0)), tonemap::srgb_oetf(c.b.clamp(0.0, 1.0))),
+            Aov::Normal => Color::new(0.5 * c.r + 0.5, 0.5 * c.g + 0.5, 0.5 * c.b + 0.5),
+            Aov::Position => Color::new(c.r.abs().fract(), c.g.abs().fract(), c.b.abs().fract()),
+            Aov::Depth => {
+                let v = if c.r.is_finite() { c.r / max } else { 0.0 };
+                Color::new(v, v, v)
+            },
+            Aov::Samples => heatmap(c.r / max),
+            Aov::ObjectId => id_color(a.object_id),
+        }).collect()
+    }
+}
+
+// blue for few samples, through green and yellow, to red for the most
+fn heatmap(t : f32) -> Color<f32> {
+    let t = t.clamp(0.0, 1.0);
+    Color::new(
+        (2.0 * t - 0.5).clamp(0.0, 1.0),
+        1.0 - (2.0 * t - 1.0).abs(),
+        (1.0 - 2.0 * t).clamp(0.0, 1.0),
+    )
+}
+
+// a random looking but stable colour for every id, black for the background
+fn id_color(id : u32) -> Color<f32> {
+    if id == 0 {
+        return Color::zero();
+    }
+    let h = (id as u64).wrapping_mul(0x9e3779b97f4a7c15);
+    let channel = |shift : u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f32 / 255.0;
+    Color::new(channel(56), channel(48), channel(40))
+}
+
+// where an AOV is written when the format can't hold more than one image: out.png -> out.albedo.png
+pub fn aov_path(path : &Path, aov : Aov) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut name = format!("{}.{}", stem, aov.name());
+    if let Some(ext) = path.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+pub struct OutputSettings {
+    pub format : OutputFormat,
+    pub tonemap : ToneMapping,
+    pub exr_precision : ExrPrecision,
+    pub aovs : Vec<Aov>,
+}
+
+// A named image plane, like "R" or "albedo.G". Rows start at the bottom of the image, like the film.
+pub struct Channel {
+    pub name : String,
+    pub data : Vec<f32>,
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Exr(exr::error::Error),
+    // the pixel data doesn't fill an image of this width and height
+    Size(usize, usize),
+}
+
+impl std::fmt::Display for OutputError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OutputError::Io(err) => write!(f, "{}", err),
+            OutputError::Image(err) => write!(f, "{}", err),
+            OutputError::Exr(err) => write!(f, "{}", err),
+            OutputError::Size(width, height) => write!(f, "the pixels don't make a {}x{} image", width, height),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+// Writes the film to `path`. 8 bit formats are tone mapped and quantized here, the float ones get the linear radiance.
+// AOVs go in the same file as extra layers for EXR, and in files of their own next to it otherwise.
+pub fn save(film : &Film, path : &Path, settings : &OutputSettings) -> Result<(), OutputError> {
+    let (width, height) = (film.width, film.height);
+    let format = settings.format;
+
+    match format {
+        OutputFormat::Exr => {
+            let mut channels = film_channels(film);
+            for aov in &settings.aovs {
+                channels.extend(aov.channels(film));
+            }
+            return write_exr(path, width, height, &channels, settings.exr_precision);
+        },
+        OutputFormat::Hdr => write_hdr(path, width, height, film.pixels())?,
+        OutputFormat::Pfm => write_pfm(path, width, height, film.pixels())?,
+        _ => write_ldr(path, width, height, film.to_rgb8(&settings.tonemap), format)?,
+    }
+
+    for aov in &settings.aovs {
+        let aov_path = aov_path(path, *aov);
+        match format {
+            OutputFormat::Hdr => write_hdr(&aov_path, width, height, &aov.values(film))?,
+            OutputFormat::Pfm => write_pfm(&aov_path, width, height, &aov.values(film))?,
+            _ => {
+                let data = aov.preview(film).iter().flat_map(|c| {
+                    let c = Color::new_from_f32(c.r.clamp(0.0, 1.0), c.g.clamp(0.0, 1.0), c.b.clamp(0.0, 1.0));
+                    [c.r, c.g, c.b]
+                }).collect();
+                write_ldr(&aov_path, width, height, data, format)?
+            },
+        }
+    }
+    Ok(())
+}
+
+// `data` is 8 bit rgb, rows from the bottom
+fn write_ldr(path : &Path, width : usize, height : usize, data : Vec<u8>, format : OutputFormat) -> Result<(), OutputError> {
+    let image_format = match format {
+        OutputFormat::Png => image::ImageFormat::Png,
+        OutputFormat::Jpeg => image::ImageFormat::Jpeg,
+        OutputFormat::Bmp => image::ImageFormat::Bmp,
+        OutputFormat::Tga => image::ImageFormat::Tga,
+        _ => unreachable!("{:?} is not an 8 bit format", format),
+    };
+
+    let mut img : image::ImageBuffer<image::Rgb<u8>, Vec<u8>> = image::ImageBuffer::from_raw(width as u32, height as u32, data)
+        .ok_or(OutputError::Size(width, height))?;
+    img = image::imageops::flip_vertical(&img);
+    img.save_with_format(path, image_format).map_err(OutputError::Image)
+}
+
+fn film_channels(film : &Film) -> Vec<Channel> {
+    let pixels = film.pixels();
+    vec![
+        Channel { name : "R".to_string(), data : pixels.iter().map(|p| p.r).collect() },
+        Channel { name : "G".to_string(), data : pixels.iter().map(|p| p.g).collect() },
+        Channel { name : "B".to_string(), data : pixels.iter().map(|p| p.b).collect() },
+    ]
+}
+
+// Single layer OpenEXR with any number of channels, compressed losslessly.
+pub fn write_exr(path : &Path, width : usize, height : usize, channels : &[Channel], precision : ExrPrecision) -> Result<(), OutputError> {
+    use exr::prelude::*;
+
+    let channels : SmallVec<[AnyChannel<FlatSamples>; 4]> = channels.iter().map(|channel| {
+        assert_eq!(channel.data.len(), width * height, "channel {} has the wrong size", channel.name);
+
+        // exr rows go from the top down
+        let top_down = channel.data.chunks(width).rev().flatten();
+        let samples = match precision {
+            ExrPrecision::Half => FlatSamples::F16(top_down.map(|v| f16::from_f32(*v)).collect()),
+            ExrPrecision::Float => FlatSamples::F32(top_down.copied().collect()),
+        };
+        AnyChannel::new(channel.name.as_str(), samples)
+    }).collect();
+
+    let layer = Layer::new(
+        (width, height),
+        LayerAttributes::default(),
+        Encoding::SMALL_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+
+    Image::from_layer(layer).write().to_file(path).map_err(OutputError::Exr)
+}
+
+// Radiance RGBE, run length encoded by the image crate. It can't store negative or infinite values, those become 0.
+fn write_hdr(path : &Path, width : usize, height : usize, pixels : &[Color<f32>]) -> Result<(), OutputError> {
+    let file = std::fs::File::create(path).map_err(OutputError::Io)?;
+
+    let positive = |v : f32| if v.is_finite() { v.max(0.0) } else { 0.0 };
+    let pixels : Vec<image::Rgb<f32>> = pixels.chunks(width).rev().flatten()
+        .map(|p| image::Rgb([positive(p.r), positive(p.g), positive(p.b)]))
+        .collect();
+
+    image::codecs::hdr::HdrEncoder::new(std::io::BufWriter::new(file))
+        .encode(&pixels, width, height)
+        .map_err(OutputError::Image)
+}
+
+// Portable float map: a tiny text header and raw little endian floats, bottom row first.
+fn write_pfm(path : &Path, width : usize, height : usize, pixels : &[Color<f32>]) -> Result<(), OutputError> {
+    let file = std::fs::File::create(path).map_err(OutputError::Io)?;
+    let mut out = std::io::BufWriter::new(file);
+
+    // a negative scale means little endian
+    write!(out, "PF\n{} {}\n-1.0\n", width, height).map_err(OutputError::Io)?;
+    for p in pixels {
+        for c in [p.r, p.g, p.b] {
+            out.write_all(&c.to_le_bytes()).map_err(OutputError::Io)?;
+        }
+    }
+    out.flush().map_err(OutputError::Io)
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::film::Aovs;
+    use crate::renderer::{Tile, RunningStats};
+    use crate::vec::Vec3;
+    use std::convert::TryInto;
+
+    // 3x2 film with a different value in every pixel, some of them above 1
+    fn test_film() -> Film {
+        let mut film = Film::new(3, 2);
+        let mut tile = Tile::new(0, 0, 3, 2);
+        tile.data = (0..6).map(|i| Color::new(i as f32 * 0.5, 1.0, 8.0)).collect();
+        tile.aovs = (0..6).map(|i| Aovs {
+            normal : Vec3::new(0.0, 1.0, 0.0),
+            depth : if i == 0 { f32::INFINITY } else { i as f32 },
+            object_id : i,
+            samples : 4,
+            ..Aovs::default()
+        }).collect();
+        tile.stats = vec![RunningStats::new(); 6];
+        tile.weights = vec![1.0; 6];
+        film.add_tile(&tile);
+        film
+    }
+
+    fn settings(format : OutputFormat, aovs : Vec<Aov>) -> OutputSettings {
+        OutputSettings { format, tonemap : ToneMapping::default(), exr_precision : ExrPrecision::Half, aovs }
+    }
+
+    fn temp_path(name : &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("raytracer-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn output_format_from_path(){
+        assert_eq!(OutputFormat::from_path(Path::new("out.EXR")), Some(OutputFormat::Exr));
+        assert_eq!(OutputFormat::from_path(Path::new("out.hdr")), Some(OutputFormat::Hdr));
+        assert_eq!(OutputFormat::from_path(Path::new("out.jpeg")), Some(OutputFormat::Jpeg));
+        assert_eq!(OutputFormat::from_path(Path::new("out.gif")), None);
+    }
+
+    #[test]
+    fn write_float_formats(){
+        let film = test_film();
+
+        for precision in [ExrPrecision::Half, ExrPrecision::Float] {
+            let path = temp_path("test.exr");
+            let mut channels = film_channels(&film);
+            channels.push(Channel { name : "depth.Z".to_string(), data : vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0] });
+            write_exr(&path, film.width, film.height, &channels, precision).unwrap();
+
+            let image = exr::prelude::read_first_flat_layer_from_file(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            let layer = &image.layer_data;
+            assert_eq!((layer.size.x(), layer.size.y()), (3, 2));
+            let names : Vec<String> = layer.channel_data.list.iter().map(|c| c.name.to_string()).collect();
+            assert_eq!(names, ["B", "G", "R", "depth.Z"]);
+
+            // the first pixel in the file is the top left one, the 4th in the film
+            let red : Vec<f32> = layer.channel_data.list[2].sample_data.values_as_f32().collect();
+            assert_eq!(red, [1.5, 2.0, 2.5, 0.0, 0.5, 1.0]);
+            let depth : Vec<f32> = layer.channel_data.list[3].sample_data.values_as_f32().collect();
+            assert_eq!(depth, [4.0, 5.0, 6.0, 1.0, 2.0, 3.0]);
+        }
+
+        let path = temp_path("test.pfm");
+        save(&film, &path, &settings(OutputFormat::Pfm, vec![])).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 3 * 2 * 3 * 4);
+        let last = f32::from_le_bytes(bytes[bytes.len() - 12..bytes.len() - 8].try_into().unwrap());
+        assert_eq!(last, 2.5);
+
+        let path = temp_path("test.hdr");
+        save(&film, &path, &settings(OutputFormat::Hdr, vec![])).unwrap();
+        let decoder = image::codecs::hdr::HdrDecoder::new(std::io::BufReader::new(std::fs::File::open(&path).unwrap())).unwrap();
+        let pixels = decoder.read_image_hdr().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(pixels.len(), 6);
+        assert_eq!(pixels[0].0, [1.5, 1.0, 8.0]);
+
+        // an error rather than a panic when the pixels don't add up
+        let err = write_ldr(&temp_path("test.png"), 3, 2, vec![0; 5], OutputFormat::Png).err().unwrap();
+        assert_eq!(err.to_string(), "the pixels don't make a 3x2 image");
+    }
+
+    #[test]
+    fn write_aovs(){
+        let film = test_film();
+        assert_eq!(aov_path(Path::new("renders/out.png"), Aov::ObjectId), Path::new("renders/out.object_id.png"));
+
+        // extra layers in the same exr
+        let path = temp_path("aovs.exr");
+        save(&film, &path, &settings(OutputFormat::Exr, vec![Aov::Normal, Aov::Depth])).unwrap();
+        let image = exr::prelude::read_first_flat_layer_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let channels = &image.layer_data.channel_data.list;
+        let names : Vec<String> = channels.iter().map(|c| c.name.to_string()).collect();
+        assert_eq!(names, ["B", "G", "R", "depth.Z", "normal.X", "normal.Y", "normal.Z"]);
+        let depth : Vec<f32> = channels[3].sample_data.values_as_f32().collect();
+        assert_eq!(&depth[3..], [f32::INFINITY, 1.0, 2.0]);
+
+        // separate files otherwise
+        for format in [OutputFormat::Png, OutputFormat::Pfm] {
+            let path = temp_path(if format == OutputFormat::Png { "aovs.png" } else { "aovs.pfm" });
+            let aovs = vec![Aov::Albedo, Aov::Normal, Aov::Position, Aov::Depth, Aov::ObjectId, Aov::Samples];
+            save(&film, &path, &settings(format, aovs.clone())).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            for aov in aovs {
+                let aov_path = aov_path(&path, aov);
+                assert!(aov_path.exists(), "{}", aov_path.display());
+                if format == OutputFormat::Png && aov == Aov::Normal {
+                    let img = image::open(&aov_path).unwrap().to_rgb8();
+                    assert_eq!(img.get_pixel(0, 0).0, [128, 255, 128]);
+                }
+                std::fs::remove_file(&aov_path).unwrap();
+            }
+        }
+
+        let (cold, hot) = (heatmap(0.0), heatmap(1.0));
+        assert_eq!((cold.r, cold.g, cold.b), (0.0, 0.0, 1.0));
+        assert_eq!((hot.r, hot.g, hot.b), (1.0, 0.0, 0.0));
+        assert_eq!(id_color(0).r, 0.0);
+        assert_ne!(id_color(1).r, id_color(2).r);
+    }
+}
@@ -0,0 +1,192 @@
+use rand::Rng;
+
+use crate::vec::Vec3;
+use crate::materials::{Lambertian, Metal, Dieletric, DiffuseLight};
+use crate::geometry::{Sphere, quad, block};
+use crate::renderer::Background;
+use crate::tonemap::ToneMapping;
+use crate::hitrecord::Hittable;
+use crate::scene::{Scene, CameraSettings, RenderSettings};
+use crate::rng;
+
+fn create_random_scene(seed : u64) -> Vec<Box<dyn Hittable + Send + Sync>> {
+    let mut objects : Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
+
+    // floor
+    let ground_material = Box::new( Lambertian::new(Vec3::new(0.5, 0.5, 0.5)) );
+    objects.push( Box::new( Sphere::new( Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground_material )));
+
+    let mut rng = rng::seeded(seed);
+    for a in -11..11 {
+        for b in -11..11 {
+            
+            let chose_mat = rng.gen::<f32>();
+
+            let center = Vec3::new( (a as f32) + 0.9 * rng.gen::<f32>(), 0.2, b as f32 + 0.9 * rng.gen::<f32>() );
+
+            if (center - Vec3::new(4.0, 0.2, 0.0) ).length() > 0.9 {
+
+                if chose_mat < 0.8 {
+
+                    let color = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * Vec3::new(rng.gen(), rng.gen(), rng.gen());
+
+                    let sphere = Sphere::new( 
+                        center,
+                        0.2, 
+                        Box::new( Lambertian::new(color) ));
+
+                    objects.push( Box::new(sphere) );  
+                }
+                else if chose_mat < 0.95 {
+                    
+                    let color = Vec3::new(rng.gen_range(0.5..1.0), rng.gen_range(0.5..1.0), rng.gen_range(0.5..1.0));
+                    let fuzz = rng.gen_range(0.0..0.5);
+                    let sphere = Sphere::new( 
+                        center,
+                        0.2, 
+                        Box::new( Metal::new(color, fuzz) ));
+
+                    objects.push( Box::new(sphere) );  
+                }
+                else
+                {
+                    let sphere = Sphere::new( 
+                        center,
+                        0.2, 
+                        Box::new( Dieletric{ ir : 1.5 } ));
+
+                    objects.push( Box::new(sphere) );
+                }
+            }
+        }
+
+
+
+
+    }
+
+    objects.push( Box::new( Sphere::new( Vec3::new(0.0, 1.0, 0.0),1.0, Box::new( Dieletric{ ir : 1.5 } ) )));      
+    objects.push( Box::new( Sphere::new( Vec3::new(-4.0, 1.0, 0.0), 1.0, Box::new( Lambertian::new(Vec3::new( 0.4, 0.2, 0.1 )))   )));  
+    objects.push( Box::new( Sphere::new( Vec3::new(4.0, 1.0, 0.0), 1.0, Box::new( Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0) ) )));  
+
+    objects
+}
+
+fn create_debug_scene() -> Vec<Box<dyn Hittable + Send + Sync>> {
+    let mut objects : Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
+
+    let floor_material = Box::new( Lambertian::new(Vec3::new(0.9, 0.9, 0.1)) );
+    // floor 
+    objects.push( Box::new( Sphere::new( Vec3::new(0.0, -100.5, -1.0), 100.0, floor_material.clone()  )));
+
+    objects.push( Box::new( Sphere::new( Vec3::new(-1.0, 0.0, -1.0),0.5, Box::new( Dieletric{ ir : 0.9 } ) )));  
+    objects.push( Box::new( Sphere::new( Vec3::new(0.0, 0.0, -1.0), 0.5, Box::new( Lambertian::new(Vec3::new(0.4, 0.4, 0.4)) ) )));  
+    objects.push( Box::new( Sphere::new( Vec3::new(1.0, 0.0, -1.0), 0.5, Box::new( Metal::new(Vec3::new(0.9, 0.8, 0.4), 0.9) ) )));  
+
+    objects
+}
+
+// the default scene, the cover of "Ray Tracing in One Weekend"
+fn random_scene(seed : u64) -> Scene {
+    Scene {
+        camera : CameraSettings {
+            look_from : Vec3::new(7.0,5.4,-5.0),
+            look_at : Vec3::new(0.0,0.0,0.0),
+            vup : Vec3::new(0.0, 1.0, 0.0),
+            vfov : 20.0,
+            aperture : 0.6,
+            focus_dist : Some(5.0),
+            shutter_open : 0.0,
+            shutter_close : 0.0,
+        },
+        render : RenderSettings {
+            width : 1500,
+            height : 750,
+            samples_per_pixel : 10,
+            max_depth : 50,
+            ..RenderSettings::default()
+        },
+        background : Background::default(),
+        tonemap : ToneMapping::default(),
+        objects : create_random_scene(seed),
+    }
+}
+
+fn debug_scene() -> Scene {
+    Scene {
+        camera : CameraSettings {
+            look_from : Vec3::new(0.0, 0.0, 1.0),
+            look_at : Vec3::new(0.0, 0.0, -1.0),
+            vup : Vec3::new(0.0, 1.0, 0.0),
+            vfov : 60.0,
+            aperture : 0.0,
+            focus_dist : None,
+            shutter_open : 0.0,
+            shutter_close : 0.0,
+        },
+        render : RenderSettings {
+            width : 800,
+            height : 400,
+            samples_per_pixel : 10,
+            max_depth : 50,
+            ..RenderSettings::default()
+        },
+        background : Background::default(),
+        tonemap : ToneMapping::default(),
+        objects : create_debug_scene(),
+    }
+}
+
+// the classic Cornell box, lit only by the light in the ceiling
+fn cornell_box_scene() -> Scene {
+    let red = Box::new( Lambertian::new(Vec3::new(0.65, 0.05, 0.05)) );
+    let white = Box::new( Lambertian::new(Vec3::new(0.73, 0.73, 0.73)) );
+    let green = Box::new( Lambertian::new(Vec3::new(0.12, 0.45, 0.15)) );
+    let light = Box::new( DiffuseLight{ emit : Vec3::new(15.0, 15.0, 15.0) } );
+
+    let mut objects : Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
+    objects.extend( quad(Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), Vec3::new(0.0, 0.0, 555.0), green) );
+    objects.extend( quad(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), Vec3::new(0.0, 0.0, 555.0), red) );
+    objects.extend( quad(Vec3::new(343.0, 554.0, 332.0), Vec3::new(-130.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -105.0), light) );
+    objects.extend( quad(Vec3::new(0.0, 0.0, 0.0), Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 555.0), white.clone()) );
+    objects.extend( quad(Vec3::new(555.0, 555.0, 555.0), Vec3::new(-555.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -555.0), white.clone()) );
+    objects.extend( quad(Vec3::new(0.0, 0.0, 555.0), Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), white.clone()) );
+
+    objects.extend( block(Vec3::new(130.0, 0.0, 65.0), Vec3::new(295.0, 165.0, 230.0), white.clone()) );
+    objects.extend( block(Vec3::new(265.0, 0.0, 295.0), Vec3::new(430.0, 330.0, 460.0), white) );
+
+    Scene {
+        camera : CameraSettings {
+            look_from : Vec3::new(278.0, 278.0, -800.0),
+            look_at : Vec3::new(278.0, 278.0, 0.0),
+            vup : Vec3::new(0.0, 1.0, 0.0),
+            vfov : 40.0,
+            aperture : 0.0,
+            focus_dist : None,
+            shutter_open : 0.0,
+            shutter_close : 0.0,
+        },
+        render : RenderSettings {
+            width : 600,
+            height : 600,
+            samples_per_pixel : 200,
+            max_depth : 50,
+            ..RenderSettings::default()
+        },
+        background : Background::None,
+        tonemap : ToneMapping::default(),
+        objects,
+    }
+}
+
+pub const PRESET_NAMES : &[&str] = &["random", "debug", "cornell"];
+
+// scenes built in code, `seed` drives the procedurally placed objects
+pub fn preset(name : &str, seed : u64) -> Option<Scene> {
+    match name {
+        "random" => Some(random_scene(seed)),
+        "debug" => Some(debug_scene()),
+        "cornell" => Some(cornell_box_scene()),
+        _ => None,
+    }
+}
@@ -1,24 +1,32 @@
 
 use crate::vec::Vec3;
 
+#[derive(Debug, Copy, Clone)]
 pub struct Ray {
    pub origin : Vec3,
    pub dir : Vec3,
+   // when the ray was cast, for things that move while the shutter is open
+   pub time : f32,
 }
 
 
 impl Ray{
 
     pub fn new(origin : Vec3, dir : Vec3) -> Self {
+        Ray::new_at(origin, dir, 0.0)
+    }
+
+    pub fn new_at(origin : Vec3, dir : Vec3, time : f32) -> Self {
         Ray {
             origin,
             dir,
+            time,
         }
     }
 
     #[allow(dead_code)]
     pub fn at(&self, t : f32) -> Vec3{
-        return self.origin + self.dir * t;
+        self.origin + self.dir * t
     }
 }
 
@@ -33,6 +41,7 @@ mod tests{
         let r = Ray{
             origin : Vec3::zero(),
             dir : Vec3::new(0., 0., 1.0),
+            time : 0.0,
         };
 
 
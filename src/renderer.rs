@@ -1,6 +1,3 @@
-extern crate rand;
-use rand::Rng;
-
 use std::sync::*;
 
 // use crate::color::Color;
@@ -8,68 +5,212 @@ use crate::color::Color;
 use crate::vec::Vec3;
 use crate::ray::Ray;
 use crate::camera::Camera;
-use crate::hitrecord::{HitRecord, Hittable, HittableList};
+use crate::hitrecord::{HitRecord, Hittable, HittableList, Identified};
+use crate::materials::Material;
+use crate::bvh::BvhNode;
+use crate::scene::{Scene, AdaptiveSampling};
+use crate::film::{Aovs, Film};
+use crate::sampler::{Sampler, SamplerKind};
+use crate::filter::Filter;
+use serde::Deserialize;
 
 
-fn ray_color(r : &Ray, hit_world : &HittableList, depth : i32) -> Vec3 {
-    
-    let mut rec = HitRecord::new();
-    
-    if depth <= 0 {
-        return Vec3::zero();
+// What a ray sees when it leaves the scene without hitting anything.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Background {
+    // black, the scene is only lit by its emissive materials
+    None,
+    Solid { color : Vec3 },
+    // blends from bottom to top based on the ray direction, the default is the sky from the book
+    Gradient { bottom : Vec3, top : Vec3 },
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Gradient {
+            bottom : Vec3::new(1.0, 1.0, 1.0),
+            top : Vec3::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl Background {
+    pub fn color(&self, r : &Ray) -> Vec3 {
+        match self {
+            Background::None => Vec3::zero(),
+            Background::Solid { color } => *color,
+            Background::Gradient { bottom, top } => {
+                let unit_vector = Vec3::normalize(r.dir);
+                let t = 0.5 * (unit_vector.y + 1.0);
+                *bottom * (1.0 - t) + *top * t
+            },
+        }
     }
+}
 
-    if hit_world.hit(r, 0.001, f32::INFINITY, &mut rec) {
+// balance between two sampling strategies, see Veach's thesis chapter 9
+fn power_heuristic(pdf : f32, other_pdf : f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b == 0.0 { 0.0 } else { a / (a + b) }
+}
 
-        let mut scattered = Ray::new(Vec3::zero(), Vec3::zero() );
-        let mut attenuation = Vec3::one();
+// Every sample uses a dimension of the sampler for the same thing, whatever the bounces before it needed:
+// the pixel position, lens and time first, then the same budget at every bounce, for the material's scatter
+// (the fresnel choice of the glass or the direction of a diffuse bounce) and for picking a light and a point on it.
+const CAMERA_DIMENSIONS : u32 = 5;
+const SCATTER_DIMENSIONS : u32 = 2;
+const LIGHT_DIMENSIONS : u32 = 3;
 
-        if let Some(m)  = rec.material.clone() {
-            if m.scatter(r, &rec, &mut attenuation, &mut scattered) {
-                return attenuation * ray_color(&scattered, &hit_world,  depth - 1);
-            }else{
-                return Vec3::zero();
-            }
+// Path tracer with next event estimation: at every diffuse bounce a point on one of the lights is sampled
+// and a shadow ray is cast towards it. Lights hit by the scattered rays are still counted, both ways of
+// reaching a light are combined with multiple importance sampling so neither is counted twice.
+// The hit of the camera ray itself is kept in `first_hit`, for the AOVs.
+fn ray_color(r : &Ray, hit_world : &dyn Hittable, lights : &HittableList, background : &Background, max_depth : i32, first_hit : &mut Option<HitRecord>, sampler : &mut dyn Sampler) -> Vec3 {
+    let mut color = Vec3::zero();
+    let mut throughput = Vec3::one();
+    let mut ray = *r;
+    // pdf of the bounce that produced `ray`, None for camera rays and specular bounces
+    let mut bsdf_pdf : Option<f32> = None;
+
+    for depth in 0..max_depth {
+        let mut rec = HitRecord::new();
+        if !hit_world.hit(&ray, 0.001, f32::INFINITY, &mut rec) {
+            color = color + throughput * background.color(&ray);
+            break;
+        }
+
+        if depth == 0 {
+            *first_hit = Some(rec.clone());
+        }
+
+        let m = match rec.material.clone() {
+            Some(m) => m,
+            None => break,
+        };
+
+        let emitted = m.emitted(&rec);
+        let weight = match bsdf_pdf {
+            Some(pdf) if m.is_emissive() => power_heuristic(pdf, lights.pdf_value(&ray.origin, &ray.dir)),
+            _ => 1.0,
+        };
+        color = color + throughput * emitted * weight;
+
+        let dimension = CAMERA_DIMENSIONS + depth as u32 * (SCATTER_DIMENSIONS + LIGHT_DIMENSIONS);
+        sampler.start_dimension(dimension);
+        let srec = match m.scatter(&ray, &rec, sampler) {
+            Some(srec) => srec,
+            None => break,
+        };
+
+        if !srec.is_specular && !lights.is_empty() {
+            let wo = -Vec3::normalize(ray.dir);
+            sampler.start_dimension(dimension + SCATTER_DIMENSIONS);
+            color = color + throughput * sample_light(&rec, &wo, ray.time, m.as_ref(), hit_world, lights, sampler);
         }
+
+        bsdf_pdf = if srec.is_specular { None } else { Some(srec.pdf) };
+        throughput = throughput * srec.value / srec.pdf;
+        ray = Ray::new_at(rec.p, srec.direction, ray.time);
+    }
+
+    color
+}
+
+// light arriving at `rec` from a point sampled on the lights, weighted against the material's own sampling
+fn sample_light(rec : &HitRecord, wo : &Vec3, time : f32, material : &dyn Material, hit_world : &dyn Hittable, lights : &HittableList, sampler : &mut dyn Sampler) -> Vec3 {
+    let wi = match lights.random(&rec.p, sampler) {
+        Some(direction) => Vec3::normalize(direction),
+        None => return Vec3::zero(),
+    };
+    let light_pdf = lights.pdf_value(&rec.p, &wi);
+    let bsdf_pdf = material.pdf(rec, &wi, wo);
+    if light_pdf <= 0.0 || bsdf_pdf <= 0.0 {
+        return Vec3::zero();
     }
 
-    let unit_vector = Vec3::normalize(r.dir);
-    let t = 0.5 * (unit_vector.y + 1.0);
-    let v =  Vec3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t; 
-    v
+    // the shadow ray, only counts if the first thing it hits is a light
+    let to_light = Ray::new_at(rec.p, wi, time);
+    let mut light_rec = HitRecord::new();
+    if !hit_world.hit(&to_light, 0.001, f32::INFINITY, &mut light_rec) {
+        return Vec3::zero();
+    }
+
+    let emitted = match &light_rec.material {
+        Some(m) if m.is_emissive() => m.emitted(&light_rec),
+        _ => return Vec3::zero(),
+    };
+
+    material.eval(rec, &wi, wo) * emitted * (power_heuristic(light_pdf, bsdf_pdf) / light_pdf)
 }
 
 pub struct RenderData {
     pub render_width : usize,
     pub render_height : usize,
+    #[allow(dead_code)]
     pub render_aspect_ratio : f32,
     
     pub max_depth : i32,
     pub samples_per_pixel : i32,
-    pub hittable : HittableList,
+    pub adaptive : Option<AdaptiveSampling>,
+    pub seed : u64,
+    pub sampler : SamplerKind,
+    pub filter : Filter,
+    pub hittable : BvhNode,
+    pub lights : HittableList,
     pub camera  : Camera,
+    pub background : Background,
 }
 
 impl RenderData{
 
-    // render_data_w.render_width = w;
-    // render_data_w.render_height = h;
-    // render_data_w.render_aspect_ratio = w as f32 / h as f32; 
+    pub fn new(scene : Scene) -> Self {
+        let width = scene.render.width;
+        let height = scene.render.height;
+        let aspect_ratio = width as f32 / height as f32;
+        let lights = scene.objects.iter().flat_map(|obj| obj.lights()).collect();
+        let objects = scene.objects.into_iter().enumerate()
+            .map(|(i, object)| Box::new(Identified { id : i as u32 + 1, object }) as Box<dyn Hittable + Send + Sync>)
+            .collect();
 
-    pub fn new(width : usize, height : usize, aspect_ratio : f32, spp : i32, ray_depth: i32 , camera : Camera, objects : Vec<Box<dyn Hittable + Send + Sync>> ) -> Self {
         RenderData{
             render_width : width,
             render_height : height,
             render_aspect_ratio : aspect_ratio,
             
-            samples_per_pixel : spp,
-            max_depth : ray_depth,
-            hittable : HittableList::new(objects),
-            camera,// Camera::new(90.0,1.0),
+            samples_per_pixel : scene.render.samples_per_pixel,
+            adaptive : scene.render.adaptive,
+            seed : scene.render.seed,
+            sampler : scene.render.sampler,
+            filter : scene.render.filter,
+            max_depth : scene.render.max_depth,
+            hittable : BvhNode::new(objects),
+            lights : HittableList::new(lights),
+            camera : scene.camera.build(aspect_ratio),
+            background : scene.background,
         }   
     }
 }
-type RenderDataHandle = RwLock<RenderData>;
+
+impl RenderData {
+    // whether a pixel with these samples needs another one
+    fn needs_samples(&self, stats : &RunningStats) -> bool {
+        match &self.adaptive {
+            None => stats.count < self.samples_per_pixel as u32,
+            Some(adaptive) => {
+                if stats.count < adaptive.min_samples.max(2) {
+                    return true;
+                }
+                // the small offset stops almost black pixels from always going to max_samples
+                let error = (stats.variance() / stats.count as f32).sqrt() / (stats.mean + 0.01);
+                stats.count < adaptive.max_samples && error > adaptive.threshold
+            },
+        }
+    }
+}
+
+pub type RenderDataHandle = RwLock<RenderData>;
 
 #[derive(Debug)]
 pub struct Tile{
@@ -79,15 +220,29 @@ pub struct Tile{
     pub w : usize,
     pub h : usize,
     
-    pub data : Vec<Color<u8>>,
+    // filter weighted sums of the new samples and of their weights, row by row over the tile and the
+    // `pad` pixels around it that the filter reaches, see splat_bounds
+    pub data : Vec<Color<f32>>,
+    pub weights : Vec<f32>,
+    pub pad : usize,
+    pub aovs : Vec<Aovs>,
+    // of every sample of the pixel, including the ones from earlier passes
+    pub stats : Vec<RunningStats>,
+    // most samples taken per pixel in one run, for progressive passes
+    pub max_samples : u32,
 }
 
 impl Tile{
     pub fn new(x : usize, y : usize, w : usize, h : usize ) -> Self {
-        let data : Vec<Color<u8>> = Vec::new();
+        let data : Vec<Color<f32>> = Vec::new();
         Tile{
             x,y,w,h,
-            data
+            data,
+            weights : Vec::new(),
+            pad : 0,
+            aovs : Vec::new(),
+            stats : Vec::new(),
+            max_samples : u32::MAX,
         }
     }
 
@@ -95,46 +250,488 @@ impl Tile{
         (self.x + x, self.y + y)
     }
 
-    pub fn run<'a>(&mut self, render_data : &'a RenderDataHandle){
-        self.data.resize(self.w * self.h, Color::black() );
+    // x, y, width and height of what `data` covers, the tile grown by `pad` and cut to the image
+    pub fn splat_bounds(&self, width : usize, height : usize) -> (usize, usize, usize, usize) {
+        let (x0, y0) = (self.x.saturating_sub(self.pad), self.y.saturating_sub(self.pad));
+        let (x1, y1) = ((self.x + self.w + self.pad).min(width), (self.y + self.h + self.pad).min(height));
+        (x0, y0, x1 - x0, y1 - y0)
+    }
+
+    // adds a sample at `offset` inside the pixel at `screen_pos` to every pixel the filter reaches
+    fn splat(&mut self, screen_pos : (usize, usize), offset : (f32, f32), color : &Vec3, filter : &Filter, bounds : (usize, usize, usize, usize)) {
+        let (x0, y0, w, h) = bounds;
+        let radius = filter.radius();
+        // pixels whose centre is within the radius, counted from the sample's pixel
+        let range = |offset : f32| ((offset - radius - 0.5).floor() as i64 + 1)..=((offset + radius - 0.5).floor() as i64);
+        for dy in range(offset.1) {
+            let y = screen_pos.1 as i64 + dy;
+            if y < y0 as i64 || y >= (y0 + h) as i64 {
+                continue;
+            }
+            for dx in range(offset.0) {
+                let x = screen_pos.0 as i64 + dx;
+                if x < x0 as i64 || x >= (x0 + w) as i64 {
+                    continue;
+                }
+                let weight = filter.evaluate(dx as f32 + 0.5 - offset.0, dy as f32 + 0.5 - offset.1);
+                if weight == 0.0 {
+                    continue;
+                }
+                let i = (y as usize - y0) * w + x as usize - x0;
+                let d = &self.data[i];
+                self.data[i] = Color::new(d.r + color.x * weight, d.g + color.y * weight, d.b + color.z * weight);
+                self.weights[i] += weight;
+            }
+        }
+    }
+
+    pub fn run(&mut self, render_data : &RenderDataHandle){
+        self.aovs.resize(self.w * self.h, Aovs::default() );
+        self.stats.resize(self.w * self.h, RunningStats::new() );
         //println!("Running on thread-id: {:?}", std::thread::current().id() ); 
 
         let world = render_data.read().unwrap();    
-        let num_of_samples = world.samples_per_pixel;
-        let mut rng = rand::thread_rng();
+        self.pad = world.filter.reach();
+        let bounds = self.splat_bounds(world.render_width, world.render_height);
+        self.data = vec![Color::zero(); bounds.2 * bounds.3];
+        self.weights = vec![0.0; bounds.2 * bounds.3];
+        // strata for the samples every pixel is sure to get, more than that and a pixel that
+        // stops early only sees some of them
+        let strata = match &world.adaptive {
+            Some(adaptive) => adaptive.min_samples,
+            None => world.samples_per_pixel as u32,
+        };
+        let mut sampler = world.sampler.build(world.seed, strata);
 
         for y in 0..self.h{
             for x in 0..self.w{
                 let screen_pos = self.world_location_of_pixel(x, y);
                 
-                let mut pixel_sample = Vec3::zero();
+                let mut aovs = AovSum::new();
+                let index = x + y * self.w;
+                let mut stats = self.stats[index];
 
-                for _ in 0..num_of_samples{
+                while aovs.samples < self.max_samples && world.needs_samples(&stats) {
+                    // counting every sample the pixel ever had, so passes and resumed renders don't repeat any
+                    sampler.start_sample(screen_pos.0, screen_pos.1, stats.count);
 
-                    let u = (screen_pos.0 as f32 + rng.gen::<f32>() ) / (world.render_width as f32 - 1.0); 
-                    let v = (screen_pos.1 as f32 + rng.gen::<f32>() ) / (world.render_height as f32  - 1.0); 
+                    let (jitter_x, jitter_y) = sampler.get_2d();
+                    let u = (screen_pos.0 as f32 + jitter_x ) / (world.render_width as f32 - 1.0); 
+                    let v = (screen_pos.1 as f32 + jitter_y ) / (world.render_height as f32  - 1.0); 
                     
-                    let r = world.camera.get_ray(u, v);
-                    pixel_sample = pixel_sample + ray_color(&r, &world.hittable, world.max_depth);
+                    let r = world.camera.get_ray(u, v, sampler.as_mut());
+                    let mut first_hit = None;
+                    let color = ray_color(&r, &world.hittable, &world.lights, &world.background, world.max_depth, &mut first_hit, sampler.as_mut());
+                    self.splat(screen_pos, (jitter_x, jitter_y), &color, &world.filter, bounds);
+                    aovs.add(&r, first_hit.as_ref(), &world.background, stats.count == 0);
+                    stats.add(luminance(&color));
                 }
 
-
-                pixel_sample = pixel_sample * (1.0 / num_of_samples as f32);
-                let index = x + y * self.w;
-                self.data[index] = Color::new_from_f32(pixel_sample.x.sqrt(), pixel_sample.y.sqrt(), pixel_sample.z.sqrt() ); //Color::new_from_f32(red, green, 0.0);
+                self.stats[index] = stats;
+                self.aovs[index] = aovs.average();
            }
         }
     }
+}
+
+fn luminance(c : &Vec3) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+// Welford's online mean and variance
+#[derive(Debug, Copy, Clone)]
+pub struct RunningStats {
+    pub count : u32,
+    pub mean : f32,
+    // sum of the squared differences from the mean
+    pub m2 : f32,
+}
+
+impl RunningStats {
+    pub fn new() -> Self {
+        RunningStats { count : 0, mean : 0.0, m2 : 0.0 }
+    }
+
+    pub fn add(&mut self, x : f32) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    // unbiased sample variance
+    pub fn variance(&self) -> f32 {
+        if self.count < 2 { 0.0 } else { self.m2 / (self.count - 1) as f32 }
+    }
+}
+
+// running sums of the AOVs of one pixel
+struct AovSum {
+    albedo : Vec3,
+    normal : Vec3,
+    position : Vec3,
+    depth : f32,
+    object_id : u32,
+    samples : u32,
+    hits : u32,
+}
+
+impl AovSum {
+    fn new() -> Self {
+        AovSum {
+            albedo : Vec3::zero(),
+            normal : Vec3::zero(),
+            position : Vec3::zero(),
+            depth : 0.0,
+            object_id : 0,
+            samples : 0,
+            hits : 0,
+        }
+    }
+
+    fn add(&mut self, r : &Ray, first_hit : Option<&HitRecord>, background : &Background, first_sample : bool) {
+        self.samples += 1;
+        match first_hit {
+            Some(rec) => {
+                if let Some(m) = &rec.material {
+                    self.albedo = self.albedo + m.albedo(rec);
+                }
+                self.normal = self.normal + rec.normal;
+                self.position = self.position + rec.p;
+                // camera rays aren't normalized, so t is scaled to a distance
+                self.depth += rec.t * r.dir.length();
+                self.hits += 1;
+                if first_sample {
+                    self.object_id = rec.object_id;
+                }
+            },
+            None => self.albedo = self.albedo + background.color(r),
+        }
+    }
 
-    pub fn write_data(&self, target : &mut Vec<u8>, w : usize, _h : usize ){
-       for y in 0..self.h{
-           for x in 0..self.w{
-               let index = x + y * self.w;
-               let index_target = (x + self.x) * 3 + (y + self.y) * 3 * w;
-               target[index_target] = self.data[index].r;
-               target[index_target + 1] = self.data[index].g;
-               target[index_target + 2] = self.data[index].b;
+    fn average(&self) -> Aovs {
+        if self.samples == 0 {
+            return Aovs::default();
+        }
+
+        let mut aovs = Aovs {
+            albedo : self.albedo / self.samples as f32,
+            object_id : self.object_id,
+            samples : self.samples,
+            ..Aovs::default()
+        };
+        if self.hits > 0 {
+            let hits = self.hits as f32;
+            if self.normal.length_squared() > 0.0 {
+                aovs.normal = Vec3::normalize(self.normal);
+            }
+            aovs.position = self.position / hits;
+            aovs.depth = self.depth / hits;
+        }
+        aovs
+    }
+}
+
+// Splits the image in tiles of tile_size x tile_size, the tiles on the right and top edges get whatever is left over.
+pub fn make_tiles(width : usize, height : usize, tile_size : usize) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(tile_size){
+        for x in (0..width).step_by(tile_size){
+            tiles.push( Tile::new(x, y, tile_size.min(width - x), tile_size.min(height - y)) );
+        }
+    }
+    tiles
+}
+
+// Renders every tile exactly once. The tiles sit in a shared queue and each thread keeps taking the next one
+// until the queue is empty, so threads that get cheap tiles end up rendering more of them.
+// The rendered tiles are returned in the order they finished.
+pub fn render_tiles(render_data : &Arc<RenderDataHandle>, mut tiles : Vec<Tile>, num_of_threads : usize) -> Vec<Tile> {
+    let num_of_tiles = tiles.len();
+
+    // popping from the back, so reverse to start with the first tile
+    tiles.reverse();
+    let queue = Arc::new(Mutex::new(tiles));
+    let (sender, receiver) = mpsc::channel();
+
+    let mut handles = Vec::with_capacity(num_of_threads);
+    for _ in 0..num_of_threads.clamp(1, num_of_tiles.max(1)) {
+        let queue = queue.clone();
+        let sender = sender.clone();
+        let render_data = render_data.clone();
+
+        handles.push( std::thread::spawn(move || {
+            loop {
+                // the lock is released as soon as the tile is taken
+                let next = queue.lock().unwrap().pop();
+                match next {
+                    Some(mut tile) => {
+                        tile.run(&render_data);
+                        sender.send(tile).unwrap();
+                    },
+                    None => break,
+                }
+            }
+        }));
+    }
+    drop(sender);
+
+    let rendered : Vec<Tile> = receiver.iter().collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(rendered.len(), num_of_tiles, "every tile must be rendered once");
+    rendered
+}
+
+// Renders the whole image once more on top of what is in the film, with at most `max_samples` new samples
+// per pixel. Returns how many samples were taken, 0 once every pixel has all the samples it needs.
+pub fn render_pass(render_data : &Arc<RenderDataHandle>, film : &mut Film, tile_size : usize, max_samples : u32, num_of_threads : usize) -> u64 {
+    let mut tiles = make_tiles(film.width, film.height, tile_size);
+    for tile in &mut tiles {
+        film.prepare_tile(tile);
+        tile.max_samples = max_samples;
+    }
+
+    // added in the same order whatever order they finished in, so the sums of the pixels the tiles share
+    // come out the same
+    let mut rendered = render_tiles(render_data, tiles, num_of_threads);
+    rendered.sort_by_key(|tile| (tile.y, tile.x));
+
+    let mut samples = 0;
+    for tile in rendered {
+        samples += tile.aovs.iter().map(|a| a.samples as u64).sum::<u64>();
+        film.add_tile(&tile);
+    }
+    samples
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests{
+    use super::*;
+    use crate::scene::{CameraSettings, RenderSettings};
+    use crate::film::Film;
+    use crate::geometry::Sphere;
+    use crate::materials::{Lambertian, Dieletric, DiffuseLight};
+    use crate::filter::FilterKind;
+
+    #[test]
+    fn tiles_cover_image(){
+        let (w, h) = (37, 23);
+        let tiles = make_tiles(w, h, 8);
+        assert_eq!(tiles.len(), 5 * 3);
+
+        let mut covered = vec![0; w * h];
+        for t in &tiles {
+            for y in t.y..t.y + t.h {
+                for x in t.x..t.x + t.w {
+                    covered[x + y * w] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|c| *c == 1));
+    }
+
+    // camera at the origin looking down -z
+    pub(crate) fn test_scene(w : usize, h : usize, samples_per_pixel : i32, objects : Vec<Box<dyn Hittable + Send + Sync>>) -> Scene {
+        Scene {
+            camera : CameraSettings {
+                look_from : Vec3::zero(),
+                look_at : Vec3::new(0.0, 0.0, -1.0),
+                vup : Vec3::new(0.0, 1.0, 0.0),
+                vfov : 90.0,
+                aperture : 0.0,
+                focus_dist : None,
+                shutter_open : 0.0,
+                shutter_close : 0.0,
+            },
+            render : RenderSettings { width : w, height : h, samples_per_pixel, max_depth : 2, ..RenderSettings::default() },
+            background : Background::default(),
+            tonemap : Default::default(),
+            objects,
+        }
+    }
+
+    #[test]
+    fn render_every_tile(){
+        let (w, h) = (37, 23);
+        let render_data = Arc::new(RwLock::new(RenderData::new(test_scene(w, h, 1, Vec::new()))));
+
+        // more threads than tiles, and an image that doesn't split evenly into tiles
+        let tiles = render_tiles(&render_data, make_tiles(w, h, 8), 16);
+        assert_eq!(tiles.len(), 15);
+
+        // the sky is never black, so every pixel must have been written
+        let mut film = Film::new(w, h);
+        for t in &tiles {
+            film.add_tile(t);
+        }
+        assert!(film.pixels().iter().all(|p| p.r > 0.0 && p.g > 0.0 && p.b > 0.0));
+    }
+
+    #[test]
+    fn render_aovs(){
+        let (w, h) = (9, 9);
+        let albedo = Vec3::new(0.2, 0.4, 0.6);
+        let objects : Vec<Box<dyn Hittable + Send + Sync>> = vec![
+            Box::new(Sphere::new(Vec3::new(0.0, 0.0, -7.0), 5.0, Box::new(Lambertian::new(albedo)))),
+        ];
+        let render_data = Arc::new(RwLock::new(RenderData::new(test_scene(w, h, 4, objects))));
+
+        let mut film = Film::new(w, h);
+        for t in render_tiles(&render_data, make_tiles(w, h, 4), 1) {
+            film.add_tile(&t);
+        }
+
+        let center = film.aovs()[4 + 4 * w];
+        assert_eq!(center.object_id, 1);
+        assert_eq!(center.samples, 4);
+        assert_eq!(center.albedo, albedo);
+        assert!((center.depth - 2.0).abs() < 0.1, "{}", center.depth);
+        assert!((center.position.z + 2.0).abs() < 0.1);
+        assert!(center.normal.z > 0.99);
+
+        let corner = film.aovs()[0];
+        assert_eq!(corner.object_id, 0);
+        assert_eq!(corner.depth, f32::INFINITY);
+        assert_eq!(corner.normal, Vec3::zero());
+    }
+
+    #[test]
+    fn running_stats(){
+        let mut stats = RunningStats::new();
+        assert_eq!(stats.variance(), 0.0);
+        for x in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.add(x);
+        }
+        assert_eq!(stats.count, 8);
+        assert!((stats.mean - 5.0).abs() < 1e-6);
+        assert!((stats.variance() - 32.0 / 7.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn adaptive_sampling(){
+        let (w, h) = (8, 8);
+        // a diffuse sphere filling the middle of the image, under the sky
+        let objects : Vec<Box<dyn Hittable + Send + Sync>> = vec![
+            Box::new(Sphere::new(Vec3::new(0.0, 0.0, -7.0), 5.0, Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))))),
+        ];
+        let mut scene = test_scene(w, h, 1, objects);
+        scene.render.adaptive = Some(AdaptiveSampling { min_samples : 4, max_samples : 64, threshold : 0.01 });
+        let render_data = Arc::new(RwLock::new(RenderData::new(scene)));
+
+        let mut film = Film::new(w, h);
+        for t in render_tiles(&render_data, make_tiles(w, h, 4), 1) {
+            film.add_tile(&t);
+        }
+
+        // the sky barely changes inside a pixel, the sphere is noisy
+        let corner = film.aovs()[0];
+        assert_eq!(corner.object_id, 0);
+        assert_eq!(corner.samples, 4);
+        let sphere : Vec<u32> = film.aovs().iter().filter(|a| a.object_id == 1).map(|a| a.samples).collect();
+        assert!(!sphere.is_empty());
+        assert!(sphere.iter().all(|s| *s >= 4 && *s <= 64));
+        assert!(sphere.iter().any(|s| *s > 4), "{:?}", sphere);
+    }
+
+    #[test]
+    fn progressive_passes(){
+        let (w, h) = (10, 6);
+        let render_data = Arc::new(RwLock::new(RenderData::new(test_scene(w, h, 5, Vec::new()))));
+
+        // 2 + 2 + 1 samples, then nothing left to do
+        let mut film = Film::new(w, h);
+        assert_eq!(render_pass(&render_data, &mut film, 4, 2, 2), 2 * (w * h) as u64);
+        assert!(film.aovs().iter().all(|a| a.samples == 2));
+        assert_eq!(render_pass(&render_data, &mut film, 4, 2, 2), 2 * (w * h) as u64);
+        assert_eq!(render_pass(&render_data, &mut film, 4, 2, 2), (w * h) as u64);
+        assert_eq!(render_pass(&render_data, &mut film, 4, 2, 2), 0);
+        assert!(film.aovs().iter().all(|a| a.samples == 5));
+        assert!(film.pixels().iter().all(|p| p.r > 0.0 && p.b > 0.0));
+    }
+
+    #[test]
+    fn deterministic_render(){
+        let (w, h) = (12, 9);
+        let render = |tile_size : usize, threads : usize, seed : u64| {
+            let objects : Vec<Box<dyn Hittable + Send + Sync>> = vec![
+                Box::new(Sphere::new(Vec3::new(-0.5, 0.0, -2.0), 0.5, Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))))),
+                Box::new(Sphere::new(Vec3::new(0.5, 0.0, -2.0), 0.5, Box::new(Dieletric { ir : 1.5 }))),
+                Box::new(Sphere::new(Vec3::new(0.0, 2.0, -2.0), 0.5, Box::new(DiffuseLight { emit : Vec3::new(4.0, 4.0, 4.0) }))),
+            ];
+            let mut scene = test_scene(w, h, 3, objects);
+            scene.render.max_depth = 6;
+            scene.render.seed = seed;
+            let render_data = Arc::new(RwLock::new(RenderData::new(scene)));
+
+            let mut film = Film::new(w, h);
+            render_pass(&render_data, &mut film, tile_size, u32::MAX, threads);
+            film.pixels().iter().map(|p| [p.r.to_bits(), p.g.to_bits(), p.b.to_bits()]).collect::<Vec<_>>()
+        };
+
+        let image = render(4, 1, 7);
+        assert_eq!(image, render(5, 3, 7));
+        assert_ne!(image, render(4, 1, 8));
+    }
+
+    #[test]
+    fn filtered_splats(){
+        let (w, h) = (10, 8);
+        let render = |filter : Filter, tile_size : usize, threads : usize| {
+            // half the image is a bright light, so the filters have an edge to spread
+            let objects : Vec<Box<dyn Hittable + Send + Sync>> = vec![
+                Box::new(Sphere::new(Vec3::new(-5.0, 0.0, -6.0), 5.0, Box::new(DiffuseLight { emit : Vec3::new(4.0, 4.0, 4.0) }))),
+            ];
+            let mut scene = test_scene(w, h, 4, objects);
+            scene.background = Background::None;
+            scene.render.filter = filter;
+            let render_data = Arc::new(RwLock::new(RenderData::new(scene)));
+
+            let mut film = Film::new(w, h);
+            render_pass(&render_data, &mut film, tile_size, u32::MAX, threads);
+            film
+        };
+
+        let kinds = [FilterKind::Box, FilterKind::Tent, FilterKind::Gaussian, FilterKind::Mitchell, FilterKind::Lanczos];
+        for kind in kinds {
+            let filter = Filter { kind, radius : None };
+            // the samples that reach into the neighbouring tiles get there, whatever the tile size
+            let whole = render(filter, 64, 1);
+            let tiled = render(filter, 3, 4);
+            for (a, b) in whole.pixels().iter().zip(tiled.pixels()) {
+                assert!((a.r - b.r).abs() < 1e-4 && (a.g - b.g).abs() < 1e-4, "{:?}: {:?} and {:?}", kind, a, b);
             }
+            // and come out the same with any number of threads
+            let again = render(filter, 3, 1);
+            assert!(tiled.pixels().iter().zip(again.pixels()).all(|(a, b)| a.r.to_bits() == b.r.to_bits()));
+            assert!(tiled.aovs().iter().all(|a| a.samples == 4));
         }
+
+        // a wider filter softens the edge of the light
+        let row = |film : &Film| (0..w).map(|x| film.pixels()[4 * w + x].r).collect::<Vec<_>>();
+        let sharp = row(&render(Filter::default(), 4, 2));
+        let soft = row(&render(Filter { kind : FilterKind::Gaussian, radius : Some(3.0) }, 4, 2));
+        let steps = |row : &[f32]| row.windows(2).map(|p| (p[1] - p[0]).abs()).fold(0.0f32, f32::max);
+        assert!(steps(&soft) < steps(&sharp), "{:?} {:?}", soft, sharp);
+    }
+
+    #[test]
+    fn background_color(){
+        let up = Ray::new(Vec3::zero(), Vec3::new(0.0, 2.0, 0.0));
+        let down = Ray::new(Vec3::zero(), Vec3::new(0.0, -1.0, 0.0));
+        let level = Ray::new(Vec3::zero(), Vec3::new(1.0, 0.0, 0.0));
+
+        assert_eq!(Background::None.color(&up), Vec3::zero());
+        let solid = Background::Solid { color : Vec3::new(0.1, 0.2, 0.3) };
+        assert_eq!(solid.color(&up), solid.color(&down));
+
+        let (bottom, top) = (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let gradient = Background::Gradient { bottom, top };
+        assert_eq!(gradient.color(&up), top);
+        assert_eq!(gradient.color(&down), bottom);
+        assert_eq!(gradient.color(&level), Vec3::new(0.5, 0.0, 0.5));
     }
 }
@@ -0,0 +1,48 @@
+use rand_pcg::Pcg32;
+
+// Random numbers of the render path. Every sample of every pixel gets a generator of its own, seeded from
+// the render seed, the pixel and the index of the sample, so the image only depends on the seed and not on
+// the number of threads or the order the tiles are rendered in.
+pub type SampleRng = Pcg32;
+
+pub fn sample_rng(seed : u64, x : usize, y : usize, sample : u32) -> SampleRng {
+    Pcg32::new(hash([seed, x as u64, y as u64, sample as u64]), STREAM)
+}
+
+// the stream has to be odd, any odd constant does
+const STREAM : u64 = 0xa02bdbf7bb3c0a7;
+
+// For what is built from a seed outside of the render path, like the random preset and the noise textures.
+// Pcg32 gives the same numbers for a seed whatever the version of rand, StdRng doesn't promise that.
+pub fn seeded(seed : u64) -> Pcg32 {
+    Pcg32::new(hash([seed]), STREAM)
+}
+
+// splitmix64 of every value in turn
+pub fn hash<const N : usize>(values : [u64; N]) -> u64 {
+    values.iter().fold(0, |h, v| {
+        let mut z = (h ^ v).wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    })
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn sample_rngs(){
+        let first = sample_rng(1, 2, 3, 4).gen::<u64>();
+        assert_eq!(first, sample_rng(1, 2, 3, 4).gen::<u64>());
+        assert_ne!(first, sample_rng(2, 2, 3, 4).gen::<u64>());
+        assert_ne!(first, sample_rng(1, 3, 2, 4).gen::<u64>());
+        assert_ne!(first, sample_rng(1, 2, 3, 5).gen::<u64>());
+
+        // pinned, so a scene built from a seed stays the same
+        assert_eq!(seeded(7).gen::<u32>(), 3367635605);
+    }
+}
@@ -0,0 +1,410 @@
+use clap::ValueEnum;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::rng::{self, SampleRng};
+
+// Where the random numbers of one sample come from. A sample asks for them one dimension at a time, the
+// pixel position first, then the lens and then a few more at every bounce. The low discrepancy samplers
+// spread the values of every dimension evenly over the samples of a pixel, which converges faster than
+// independent random numbers.
+pub trait Sampler {
+    // starts the `index`th sample of a pixel, from the first dimension
+    fn start_sample(&mut self, x : usize, y : usize, index : u32);
+    // carries on from `dimension`, so that a dimension is used for the same thing by all the samples of a pixel
+    fn start_dimension(&mut self, dimension : u32);
+    fn get_1d(&mut self) -> f32;
+    fn get_2d(&mut self) -> (f32, f32);
+    // for the things that need an unknown amount of random numbers, like rejection sampling
+    fn rng(&mut self) -> &mut SampleRng;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    #[default]
+    Independent,
+    // jittered strata, as many as the samples per pixel
+    Stratified,
+    // with a random shift per pixel
+    Halton,
+    // Owen scrambled and shuffled, see Burley 2020
+    Sobol,
+}
+
+impl SamplerKind {
+    // `strata` is how many samples the stratified sampler splits every dimension in, every run of that many
+    // samples of a pixel goes through all of them
+    pub fn build(&self, seed : u64, strata : u32) -> Box<dyn Sampler> {
+        let state = SampleState::new(seed);
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler { state }),
+            SamplerKind::Stratified => Box::new(StratifiedSampler { state, samples : strata.max(1) }),
+            SamplerKind::Halton => Box::new(HaltonSampler { state }),
+            SamplerKind::Sobol => Box::new(SobolSampler { state }),
+        }
+    }
+}
+
+// what every sampler keeps track of
+struct SampleState {
+    seed : u64,
+    // hash of the seed and the pixel, so every pixel gets different points
+    pixel_seed : u64,
+    index : u32,
+    dimension : u32,
+    rng : SampleRng,
+}
+
+impl SampleState {
+    fn new(seed : u64) -> Self {
+        SampleState {
+            seed,
+            pixel_seed : 0,
+            index : 0,
+            dimension : 0,
+            rng : rng::sample_rng(seed, 0, 0, 0),
+        }
+    }
+
+    fn start(&mut self, x : usize, y : usize, index : u32) {
+        self.pixel_seed = rng::hash([self.seed, x as u64, y as u64]);
+        self.index = index;
+        self.dimension = 0;
+        self.rng = rng::sample_rng(self.seed, x, y, index);
+    }
+
+    // seed of the next `count` dimensions
+    fn next_dimension(&mut self, count : u32) -> (u32, u64) {
+        let dimension = self.dimension;
+        self.dimension += count;
+        (dimension, rng::hash([self.pixel_seed, dimension as u64]))
+    }
+}
+
+pub struct IndependentSampler {
+    state : SampleState,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, x : usize, y : usize, index : u32) {
+        self.state.start(x, y, index);
+    }
+
+    // the dimensions don't mean anything to it
+    fn start_dimension(&mut self, _dimension : u32) {}
+
+    fn get_1d(&mut self) -> f32 {
+        self.state.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.state.rng.gen(), self.state.rng.gen())
+    }
+
+    fn rng(&mut self) -> &mut SampleRng {
+        &mut self.state.rng
+    }
+}
+
+// Every dimension is split in `samples` strata, and the samples of a pixel go through them in an order
+// shuffled differently for every dimension, so that the dimensions don't line up with each other.
+pub struct StratifiedSampler {
+    state : SampleState,
+    samples : u32,
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x : usize, y : usize, index : u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn start_dimension(&mut self, dimension : u32) {
+        self.state.dimension = dimension;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (_, seed) = self.state.next_dimension(1);
+        let stratum = permute(self.state.index % self.samples, self.samples, seed as u32);
+        (stratum as f32 + self.state.rng.gen::<f32>()) / self.samples as f32
+    }
+
+    // on a square grid, with as many cells as there are samples or a few more
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (_, seed) = self.state.next_dimension(2);
+        let side = (self.samples as f32).sqrt().ceil() as u32;
+        let cells = side * side;
+        let stratum = permute(self.state.index % cells, cells, seed as u32);
+        (
+            ((stratum % side) as f32 + self.state.rng.gen::<f32>()) / side as f32,
+            ((stratum / side) as f32 + self.state.rng.gen::<f32>()) / side as f32,
+        )
+    }
+
+    fn rng(&mut self) -> &mut SampleRng {
+        &mut self.state.rng
+    }
+}
+
+const PRIMES : [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+// The Halton sequence of every pixel, with a different random shift (a Cranley-Patterson rotation) per pixel
+// and dimension. Dimensions past the prime table are independent random numbers.
+pub struct HaltonSampler {
+    state : SampleState,
+}
+
+impl HaltonSampler {
+    fn sample(&mut self, dimension : u32, seed : u64) -> f32 {
+        match PRIMES.get(dimension as usize) {
+            Some(base) => {
+                let shift = (seed >> 40) as f32 / (1u64 << 24) as f32;
+                let v = radical_inverse(self.state.index, *base) + shift;
+                to_unit(if v >= 1.0 { v - 1.0 } else { v })
+            },
+            None => self.state.rng.gen(),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x : usize, y : usize, index : u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn start_dimension(&mut self, dimension : u32) {
+        self.state.dimension = dimension;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (dimension, seed) = self.state.next_dimension(1);
+        self.sample(dimension, seed)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (dimension, seed) = self.state.next_dimension(2);
+        (self.sample(dimension, seed), self.sample(dimension + 1, rng::hash([seed, 1])))
+    }
+
+    fn rng(&mut self) -> &mut SampleRng {
+        &mut self.state.rng
+    }
+}
+
+// digits of `index` in `base`, mirrored around the decimal point
+fn radical_inverse(mut index : u32, base : u32) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv = inv_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f64 * inv;
+        index /= base;
+        inv *= inv_base;
+    }
+    result as f32
+}
+
+// The first two Sobol dimensions, Owen scrambled, for every pair of dimensions. Every pair gets its own
+// scramble and its own shuffle of the sample order, so different pairs don't correlate. "Practical
+// Hash-based Owen Scrambling", Burley 2020.
+pub struct SobolSampler {
+    state : SampleState,
+}
+
+impl SobolSampler {
+    fn sample_2d(&self, seed : u64) -> (f32, f32) {
+        let seed = seed as u32;
+        let index = nested_uniform_scramble(self.state.index, seed);
+        let x = nested_uniform_scramble(sobol_0(index), hash_u32(seed, 0));
+        let y = nested_uniform_scramble(sobol_1(index), hash_u32(seed, 1));
+        (bits_to_unit(x), bits_to_unit(y))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x : usize, y : usize, index : u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn start_dimension(&mut self, dimension : u32) {
+        self.state.dimension = dimension;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (_, seed) = self.state.next_dimension(1);
+        self.sample_2d(seed).0
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (_, seed) = self.state.next_dimension(2);
+        self.sample_2d(seed)
+    }
+
+    fn rng(&mut self) -> &mut SampleRng {
+        &mut self.state.rng
+    }
+}
+
+// the first Sobol dimension is the van der Corput sequence
+fn sobol_0(index : u32) -> u32 {
+    index.reverse_bits()
+}
+
+// the second one, built from the polynomial x + 1
+fn sobol_1(mut index : u32) -> u32 {
+    let mut result = 0;
+    let mut v = 1u32 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+// Owen scrambling of the bits of x: every bit flips based on the bits above it
+fn nested_uniform_scramble(x : u32, seed : u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x : u32, seed : u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn hash_u32(seed : u32, value : u64) -> u32 {
+    rng::hash([seed as u64, value]) as u32
+}
+
+fn bits_to_unit(x : u32) -> f32 {
+    to_unit((x >> 8) as f32 / (1u32 << 24) as f32)
+}
+
+// keeps rounding from ever giving 1
+fn to_unit(v : f32) -> f32 {
+    v.min(1.0 - f32::EPSILON / 2.0)
+}
+
+// A random permutation of 0..len picked by `seed`, without storing it. "Correlated Multi-Jittered Sampling", Kensler 2013.
+fn permute(mut i : u32, len : u32, seed : u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % len
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const KINDS : [SamplerKind; 4] = [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+
+    // the 2D points of `dimension` for the first `n` samples of a pixel
+    fn points(kind : SamplerKind, n : u32, dimension : u32) -> Vec<(f32, f32)> {
+        let mut sampler = kind.build(3, n);
+        (0..n).map(|i| {
+            sampler.start_sample(5, 7, i);
+            for _ in 0..dimension {
+                sampler.get_1d();
+            }
+            sampler.get_2d()
+        }).collect()
+    }
+
+    #[test]
+    fn sampler_values(){
+        for kind in KINDS {
+            for dimension in [0, 1, 5, 70] {
+                let points = points(kind, 64, dimension);
+                assert!(points.iter().all(|(x, y)| (0.0..1.0).contains(x) && (0.0..1.0).contains(y)), "{:?}", kind);
+                assert_eq!(points, self::points(kind, 64, dimension));
+
+                // a smooth integral, the low discrepancy samplers get a lot closer
+                let estimate = points.iter().map(|(x, y)| x * y).sum::<f32>() / 64.0;
+                // Halton runs out of primes after 32 dimensions
+                let low_discrepancy = kind != SamplerKind::Independent && !(kind == SamplerKind::Halton && dimension >= 32);
+                let tolerance = if low_discrepancy { 0.02 } else { 0.1 };
+                assert!((estimate - 0.25).abs() < tolerance, "{:?} {} {}", kind, dimension, estimate);
+            }
+        }
+    }
+
+    #[test]
+    fn sampler_strata(){
+        // one point in every cell of a 4x4 grid
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol] {
+            for dimension in [0, 3] {
+                let mut cells = [0; 16];
+                for (x, y) in points(kind, 16, dimension) {
+                    cells[(x * 4.0) as usize + 4 * (y * 4.0) as usize] += 1;
+                }
+                assert!(cells.iter().all(|c| *c == 1), "{:?} {:?}", kind, cells);
+            }
+        }
+
+        // skipping to a dimension gives the same points as going through the ones before it
+        for kind in [SamplerKind::Halton, SamplerKind::Sobol] {
+            let mut sampler = kind.build(3, 16);
+            let skipped : Vec<(f32, f32)> = (0..16).map(|i| {
+                sampler.start_sample(5, 7, i);
+                sampler.get_1d();
+                sampler.start_dimension(3);
+                sampler.get_2d()
+            }).collect();
+            assert_eq!(skipped, points(kind, 16, 3), "{:?}", kind);
+        }
+
+        let mut sampler = SamplerKind::Stratified.build(1, 10);
+        let mut strata = [0; 10];
+        for i in 0..10 {
+            sampler.start_sample(0, 0, i);
+            strata[(sampler.get_1d() * 10.0) as usize] += 1;
+        }
+        assert!(strata.iter().all(|s| *s == 1));
+
+        for len in [1, 7, 16, 100] {
+            let mut seen : Vec<u32> = (0..len).map(|i| permute(i, len, 12345)).collect();
+            seen.sort();
+            assert_eq!(seen, (0..len).collect::<Vec<_>>());
+        }
+        assert_eq!(radical_inverse(6, 2), 0.375);
+        assert_eq!(sobol_1(3), 0x40000000);
+    }
+}
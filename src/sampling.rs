@@ -0,0 +1,233 @@
+use std::f32::consts::PI;
+
+use crate::vec::Vec3;
+
+// Warps from uniform numbers in [0, 1)^2 to the distributions the renderer samples, each with its pdf.
+// Directions are in a local frame around +z, `to_world` moves them around a normal.
+
+pub fn to_world(v : Vec3, n : &Vec3) -> Vec3 {
+    let (t, b) = Vec3::orthonormal_basis(n);
+    t * v.x + b * v.y + *n * v.z
+}
+
+pub fn uniform_sphere(u : (f32, f32)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_sphere_pdf() -> f32 {
+    1.0 / (4.0 * PI)
+}
+
+// nothing in the renderer samples the hemisphere uniformly yet, the tests use it as a reference
+#[allow(dead_code)]
+pub fn uniform_hemisphere(u : (f32, f32)) -> Vec3 {
+    let z = u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[allow(dead_code)]
+pub fn uniform_hemisphere_pdf() -> f32 {
+    1.0 / (2.0 * PI)
+}
+
+// Shirley and Chiu's mapping, keeps the strata of u in one piece unlike the polar one
+pub fn concentric_disk(u : (f32, f32)) -> (f32, f32) {
+    let (x, y) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, PI / 4.0 * (y / x))
+    } else {
+        (y, PI / 2.0 - PI / 4.0 * (x / y))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+// the lens doesn't need it, every point on it is as likely
+#[allow(dead_code)]
+pub fn concentric_disk_pdf() -> f32 {
+    1.0 / PI
+}
+
+// the disk lifted onto the hemisphere (Malley's method)
+pub fn cosine_hemisphere(u : (f32, f32)) -> Vec3 {
+    let (x, y) = concentric_disk(u);
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    Vec3::new(x, y, z)
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta : f32) -> f32 {
+    cos_theta.max(0.0) / PI
+}
+
+// barycentric coordinates of a point spread evenly over a triangle
+pub fn uniform_triangle(u : (f32, f32)) -> [f32; 3] {
+    let su0 = u.0.sqrt();
+    let b0 = 1.0 - su0;
+    let b1 = u.1 * su0;
+    [b0, b1, 1.0 - b0 - b1]
+}
+
+// over the triangle's area
+pub fn uniform_triangle_pdf(area : f32) -> f32 {
+    1.0 / area
+}
+
+// directions within the angle acos(cos_theta_max) of +z
+pub fn uniform_cone(u : (f32, f32), cos_theta_max : f32) -> Vec3 {
+    let z = 1.0 + u.0 * (cos_theta_max - 1.0);
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_cone_pdf(cos_theta_max : f32) -> f32 {
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use rand::Rng;
+    use std::f64::consts::PI;
+
+    const SAMPLES : usize = 200_000;
+
+    // Pearson's chi-square test of `samples` in [x0, x1] x [y0, y1] against `density`, on a grid of
+    // nx by ny cells. Cells expecting fewer than 5 samples are pooled. The p-value comes from the
+    // Wilson-Hilferty approximation, which is close enough at these degrees of freedom.
+    fn chi_square(samples : &[(f64, f64)], (x0, x1) : (f64, f64), (y0, y1) : (f64, f64), (nx, ny) : (usize, usize), density : impl Fn(f64, f64) -> f64) -> bool {
+        let (dx, dy) = ((x1 - x0) / nx as f64, (y1 - y0) / ny as f64);
+        let mut observed = vec![0.0; nx * ny];
+        for (x, y) in samples {
+            let i = (((x - x0) / dx) as usize).min(nx - 1);
+            let j = (((y - y0) / dy) as usize).min(ny - 1);
+            observed[i + j * nx] += 1.0;
+        }
+
+        // midpoint rule on a finer grid in every cell
+        const SUB : usize = 16;
+        let expected : Vec<f64> = (0..nx * ny).map(|cell| {
+            let (cx, cy) = (x0 + (cell % nx) as f64 * dx, y0 + (cell / nx) as f64 * dy);
+            let mut sum = 0.0;
+            for a in 0..SUB {
+                for b in 0..SUB {
+                    sum += density(cx + (a as f64 + 0.5) * dx / SUB as f64, cy + (b as f64 + 0.5) * dy / SUB as f64);
+                }
+            }
+            sum * dx * dy / (SUB * SUB) as f64 * samples.len() as f64
+        }).collect();
+
+        let (mut chi2, mut cells) = (0.0, 0);
+        let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+        for (o, e) in observed.iter().zip(&expected) {
+            if *e < 5.0 {
+                pooled_observed += o;
+                pooled_expected += e;
+            } else {
+                chi2 += (o - e) * (o - e) / e;
+                cells += 1;
+            }
+        }
+        if pooled_expected >= 5.0 {
+            chi2 += (pooled_observed - pooled_expected) * (pooled_observed - pooled_expected) / pooled_expected;
+            cells += 1;
+        } else if pooled_observed > 10.0 {
+            // samples where the density says there should be none
+            return false;
+        }
+
+        let k = (cells - 1) as f64;
+        let z = ((chi2 / k).powf(1.0 / 3.0) - (1.0 - 2.0 / (9.0 * k))) / (2.0 / (9.0 * k)).sqrt();
+        // one sided, p < 0.001
+        z < 3.09
+    }
+
+    fn uniform_samples(seed : u64, warp : impl Fn((f32, f32)) -> (f64, f64)) -> Vec<(f64, f64)> {
+        let mut rng = crate::rng::sample_rng(seed, 0, 0, 0);
+        (0..SAMPLES).map(|_| warp((rng.gen(), rng.gen()))).collect()
+    }
+
+    // (theta, phi) of a direction, phi in [0, 2pi)
+    fn spherical(v : Vec3) -> (f64, f64) {
+        let theta = (v.z as f64).clamp(-1.0, 1.0).acos();
+        let phi = (v.y as f64).atan2(v.x as f64);
+        (theta, if phi < 0.0 { phi + 2.0 * PI } else { phi })
+    }
+
+    #[test]
+    fn warp_distributions(){
+        // the densities in (theta, phi) are the solid angle pdfs times sin(theta)
+        let sphere = uniform_samples(1, |u| spherical(uniform_sphere(u)));
+        assert!(chi_square(&sphere, (0.0, PI), (0.0, 2.0 * PI), (10, 20), |t, _| uniform_sphere_pdf() as f64 * t.sin()));
+
+        let hemisphere = uniform_samples(2, |u| spherical(uniform_hemisphere(u)));
+        assert!(chi_square(&hemisphere, (0.0, PI / 2.0), (0.0, 2.0 * PI), (10, 20), |t, _| uniform_hemisphere_pdf() as f64 * t.sin()));
+
+        let cosine = uniform_samples(3, |u| spherical(cosine_hemisphere(u)));
+        assert!(chi_square(&cosine, (0.0, PI / 2.0), (0.0, 2.0 * PI), (10, 20),
+            |t, _| cosine_hemisphere_pdf(t.cos() as f32) as f64 * t.sin()));
+
+        let cos_theta_max = 0.8f32;
+        let cone = uniform_samples(4, |u| spherical(uniform_cone(u, cos_theta_max)));
+        assert!(chi_square(&cone, (0.0, (cos_theta_max as f64).acos()), (0.0, 2.0 * PI), (10, 20),
+            |t, _| uniform_cone_pdf(cos_theta_max) as f64 * t.sin()));
+
+        // the disk in polar coordinates, r dr dphi
+        let disk = uniform_samples(5, |u| {
+            let (x, y) = concentric_disk(u);
+            let phi = (y as f64).atan2(x as f64);
+            ((x as f64).hypot(y as f64), if phi < 0.0 { phi + 2.0 * PI } else { phi })
+        });
+        assert!(chi_square(&disk, (0.0, 1.0), (0.0, 2.0 * PI), (10, 20), |r, _| concentric_disk_pdf() as f64 * r));
+
+        // the triangle (0,0) (1,0) (0,1), with b1 / (1 - b0) in place of b1 so the cells fit it, dA = (1 - b0) db0 dt
+        let triangle = uniform_samples(6, |u| {
+            let [b0, b1, _] = uniform_triangle(u);
+            (b0 as f64, b1 as f64 / (1.0 - b0 as f64).max(1e-12))
+        });
+        assert!(chi_square(&triangle, (0.0, 1.0), (0.0, 1.0), (16, 16), |b0, _| uniform_triangle_pdf(0.5) as f64 * (1.0 - b0)));
+    }
+
+    #[test]
+    fn warp_rejects_wrong_distributions(){
+        // what random_in_unit_sphere used to do, one octant only
+        let octant = uniform_samples(7, |u| spherical(uniform_sphere((u.0 * 0.5, u.1 * 0.25))));
+        assert!(!chi_square(&octant, (0.0, PI), (0.0, 2.0 * PI), (10, 20), |t, _| uniform_sphere_pdf() as f64 * t.sin()));
+
+        // uniform directions aren't cosine weighted
+        let hemisphere = uniform_samples(8, |u| spherical(uniform_hemisphere(u)));
+        assert!(!chi_square(&hemisphere, (0.0, PI / 2.0), (0.0, 2.0 * PI), (10, 20),
+            |t, _| cosine_hemisphere_pdf(t.cos() as f32) as f64 * t.sin()));
+    }
+
+    #[test]
+    fn warp_values(){
+        let mut rng = crate::rng::sample_rng(9, 0, 0, 0);
+        for _ in 0..1000 {
+            let u = (rng.gen(), rng.gen());
+            for v in [uniform_sphere(u), uniform_hemisphere(u), cosine_hemisphere(u), uniform_cone(u, 0.5)] {
+                assert!((v.length() - 1.0).abs() < 1e-4);
+            }
+            assert!(uniform_hemisphere(u).z >= 0.0 && cosine_hemisphere(u).z >= 0.0);
+            assert!(uniform_cone(u, 0.5).z >= 0.5 - 1e-6);
+
+            let (x, y) = concentric_disk(u);
+            assert!(x * x + y * y <= 1.0 + 1e-5);
+            let b = uniform_triangle(u);
+            assert!(b.iter().all(|b| *b >= -1e-6) && (b.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        }
+
+        let n = Vec3::normalize(Vec3::new(1.0, -2.0, 0.5));
+        assert!((to_world(Vec3::new(0.0, 0.0, 1.0), &n) - n).length() < 1e-5);
+        assert!(Vec3::dot(&to_world(Vec3::new(1.0, 0.0, 0.0), &n), &n).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,847 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::vec::Vec3;
+use crate::camera::Camera;
+use crate::hitrecord::Hittable;
+use crate::sampler::SamplerKind;
+use crate::filter::{self, Filter};
+use crate::geometry::{Sphere, MovingSphere, Triangle, quad, block};
+use crate::materials::{Material, Lambertian, Metal, Dieletric, DiffuseLight};
+use crate::texture::{Texture, SolidColor, CheckerTexture, ImageTexture, NoiseTexture, NoisePattern};
+use crate::renderer::Background;
+use crate::tonemap::ToneMapping;
+use crate::obj::{self, ObjError};
+use crate::bvh::BvhNode;
+use crate::transform::{Transform, Transformed};
+
+// Everything needed to render a frame, either loaded from a scene file or built by a preset.
+pub struct Scene {
+    pub camera : CameraSettings,
+    pub render : RenderSettings,
+    pub background : Background,
+    pub tonemap : ToneMapping,
+    pub objects : Vec<Box<dyn Hittable + Send + Sync>>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid { path : PathBuf, line : Option<usize>, message : String },
+    Mesh(ObjError),
+    // given on the command line, neither an existing file nor the name of a preset
+    Unknown(String),
+    // render settings the command line overrides made invalid
+    Settings(String),
+}
+
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SceneError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            SceneError::Parse(path, err) => write!(f, "{}: {}", path.display(), err),
+            SceneError::Invalid { path, line : Some(line), message } => write!(f, "{}:{}: {}", path.display(), line, message),
+            SceneError::Invalid { path, line : None, message } => write!(f, "{}: {}", path.display(), message),
+            SceneError::Mesh(err) => write!(f, "{}", err),
+            SceneError::Unknown(name) => write!(f, "`{}` is neither a scene file nor a preset ({})", name, crate::presets::PRESET_NAMES.join(", ")),
+            SceneError::Settings(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+
+// File format -----
+// Vectors are written as [x, y, z] arrays, see scenes/debug.toml for an example.
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    // spanned so errors found after parsing can still point at the line of the table
+    camera : Spanned<CameraSettings>,
+    #[serde(default)]
+    render : Option<Spanned<RenderSettings>>,
+    #[serde(default)]
+    background : Background,
+    #[serde(default)]
+    tonemap : ToneMapping,
+    #[serde(default)]
+    textures : HashMap<String, Spanned<TextureDesc>>,
+    #[serde(default)]
+    materials : HashMap<String, Spanned<MaterialDesc>>,
+    #[serde(default)]
+    objects : Vec<Spanned<ObjectDesc>>,
+}
+
+// arguments of Camera::new, the aspect ratio comes from the render resolution
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraSettings {
+    pub look_from : Vec3,
+    pub look_at : Vec3,
+    #[serde(default = "default_vup")]
+    pub vup : Vec3,
+    pub vfov : f32,
+    #[serde(default)]
+    pub aperture : f32,
+    // defaults to the distance between look_from and look_at
+    pub focus_dist : Option<f32>,
+    // times the shutter opens and closes, objects that move in between come out blurred
+    #[serde(default)]
+    pub shutter_open : f32,
+    #[serde(default)]
+    pub shutter_close : f32,
+}
+
+impl CameraSettings {
+    pub fn build(&self, aspect_ratio : f32) -> Camera {
+        let focus_dist = self.focus_dist.unwrap_or_else(|| (self.look_from - self.look_at).length());
+        Camera::new(
+            self.look_from,
+            self.look_at,
+            self.vup,
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            focus_dist,
+        ).with_shutter(self.shutter_open, self.shutter_close)
+    }
+}
+
+fn default_vup() -> Vec3 {
+    Vec3::new(0.0, 1.0, 0.0)
+}
+
+// the RenderData fields, can be overridden from the command line
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderSettings {
+    pub width : usize,
+    pub height : usize,
+    pub samples_per_pixel : i32,
+    pub max_depth : i32,
+    // run the denoiser on the finished image
+    #[serde(default)]
+    pub denoise : bool,
+    // when set samples_per_pixel is ignored, every pixel gets as many samples as it needs
+    #[serde(default)]
+    pub adaptive : Option<AdaptiveSampling>,
+    // render the whole image in passes, writing what there is so far after them
+    #[serde(default)]
+    pub progressive : Option<Progressive>,
+    // the same seed gives the same camera samples
+    #[serde(default)]
+    pub seed : u64,
+    // where the random numbers of the samples come from
+    #[serde(default)]
+    pub sampler : SamplerKind,
+    // how the samples are spread over the pixels around them
+    #[serde(default)]
+    pub filter : Filter,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Progressive {
+    // samples per pixel added by every pass
+    pub pass_samples : u32,
+    // seconds between snapshots, when not set one is written after every pass
+    pub snapshot_interval : Option<f32>,
+}
+
+impl Default for Progressive {
+    fn default() -> Self {
+        Progressive {
+            pass_samples : 4,
+            snapshot_interval : None,
+        }
+    }
+}
+
+// Keeps sampling a pixel until the standard error of its mean luminance, relative to the luminance,
+// goes below `threshold`, with at least `min_samples` and at most `max_samples` samples.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdaptiveSampling {
+    pub min_samples : u32,
+    pub max_samples : u32,
+    pub threshold : f32,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        AdaptiveSampling {
+            min_samples : 16,
+            max_samples : 1024,
+            threshold : 0.02,
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            width : 800,
+            height : 400,
+            samples_per_pixel : 10,
+            max_depth : 50,
+            denoise : false,
+            adaptive : None,
+            progressive : None,
+            seed : 0,
+            sampler : SamplerKind::Independent,
+            filter : Filter::default(),
+        }
+    }
+}
+
+impl RenderSettings {
+    // also run after the command line overrides, which can break settings that were fine in the file
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err(format!("render.width and render.height must be greater than 0, found {}x{}", self.width, self.height));
+        }
+        // TOML integers are i64, bigger seeds couldn't be written to a checkpoint
+        if self.seed > i64::MAX as u64 {
+            return Err(format!("render.seed must be at most {}, found {}", i64::MAX, self.seed));
+        }
+        if self.samples_per_pixel <= 0 {
+            return Err(format!("render.samples_per_pixel must be greater than 0, found {}", self.samples_per_pixel));
+        }
+        if self.max_depth <= 0 {
+            return Err(format!("render.max_depth must be greater than 0, found {}", self.max_depth));
+        }
+        if !(filter::MIN_RADIUS..=filter::MAX_RADIUS).contains(&self.filter.radius()) {
+            return Err(format!("render.filter.radius must be between {} and {}, found {}", filter::MIN_RADIUS, filter::MAX_RADIUS, self.filter.radius()));
+        }
+        if let Some(adaptive) = &self.adaptive {
+            if adaptive.min_samples == 0 || adaptive.max_samples < adaptive.min_samples {
+                return Err(format!("render.adaptive needs 0 < min_samples <= max_samples, found {} and {}", adaptive.min_samples, adaptive.max_samples));
+            }
+            // written so NaN fails too
+            if !(adaptive.threshold >= 0.0 && adaptive.threshold.is_finite()) {
+                return Err(format!("render.adaptive.threshold must be 0 or more, found {}", adaptive.threshold));
+            }
+        }
+        if let Some(progressive) = &self.progressive {
+            if progressive.pass_samples == 0 {
+                return Err("render.progressive.pass_samples must be greater than 0, found 0".to_string());
+            }
+            match progressive.snapshot_interval {
+                Some(seconds) if !(seconds >= 0.0 && seconds.is_finite()) => {
+                    return Err(format!("render.progressive.snapshot_interval must be 0 or more seconds, found {}", seconds));
+                },
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    // 3D checker with cubes of `scale` units
+    Checker { scale : f32, even : Vec3, odd : Vec3 },
+    // relative paths are resolved from the scene file directory
+    Image { path : PathBuf },
+    // procedural, blends from `low` to `high` following the pattern
+    Noise {
+        pattern : NoisePattern,
+        #[serde(default = "default_noise_scale")]
+        scale : f32,
+        #[serde(default = "Vec3::zero")]
+        low : Vec3,
+        #[serde(default = "Vec3::one")]
+        high : Vec3,
+        #[serde(default)]
+        seed : u64,
+    },
+}
+
+fn default_noise_scale() -> f32 {
+    1.0
+}
+
+impl TextureDesc {
+    fn build(&self, base_dir : &Path) -> Result<Arc<dyn Texture>, String> {
+        match self {
+            TextureDesc::Checker { scale, even, odd } => {
+                if *scale <= 0.0 {
+                    return Err(format!("scale must be greater than 0, found {}", scale));
+                }
+                Ok(Arc::new( CheckerTexture{ scale : *scale, even : SolidColor::shared(*even), odd : SolidColor::shared(*odd) } ))
+            },
+            TextureDesc::Image { path } => {
+                let path = base_dir.join(path);
+                match ImageTexture::load(&path) {
+                    Ok(texture) => Ok(Arc::new(texture)),
+                    Err(err) => Err(format!("can't load `{}`: {}", path.display(), err)),
+                }
+            },
+            TextureDesc::Noise { pattern, scale, low, high, seed } => {
+                Ok(Arc::new( NoiseTexture::new(*pattern, *scale, *low, *high, *seed) ))
+            },
+        }
+    }
+}
+
+// albedos are either a colour or the name of one of the textures
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a colour [r, g, b] or the name of a texture")]
+enum TextureRef {
+    Color(Vec3),
+    Named(String),
+}
+
+impl TextureRef {
+    fn build(&self, textures : &HashMap<String, Arc<dyn Texture>>) -> Result<Arc<dyn Texture>, String> {
+        match self {
+            TextureRef::Color(color) => Ok(SolidColor::shared(*color)),
+            TextureRef::Named(name) => textures.get(name).cloned().ok_or_else(|| format!("unknown texture `{}`", name)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian { albedo : TextureRef },
+    Metal { albedo : TextureRef, #[serde(default)] fuzz : f32 },
+    Dielectric { ir : f32 },
+    DiffuseLight { emit : Vec3 },
+}
+
+impl MaterialDesc {
+    fn build(&self, textures : &HashMap<String, Arc<dyn Texture>>) -> Result<Box<dyn Material + Send + Sync>, String> {
+        Ok(match self {
+            MaterialDesc::Lambertian { albedo } => Box::new( Lambertian{ albedo : albedo.build(textures)? } ),
+            MaterialDesc::Metal { albedo, fuzz } => Box::new( Metal{ albedo : albedo.build(textures)?, fuzz : *fuzz } ),
+            MaterialDesc::Dielectric { ir } => Box::new( Dieletric{ ir : *ir } ),
+            MaterialDesc::DiffuseLight { emit } => Box::new( DiffuseLight{ emit : *emit } ),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center : Vec3,
+        radius : f32,
+        material : String,
+        #[serde(default)]
+        transform : Option<TransformDesc>,
+    },
+    // at center0 at time0 and at center1 at time1
+    MovingSphere {
+        center0 : Vec3,
+        center1 : Vec3,
+        #[serde(default)]
+        time0 : f32,
+        #[serde(default = "default_time1")]
+        time1 : f32,
+        radius : f32,
+        material : String,
+        #[serde(default)]
+        transform : Option<TransformDesc>,
+    },
+    Triangle {
+        vertices : [Vec3; 3],
+        material : String,
+        #[serde(default)]
+        transform : Option<TransformDesc>,
+    },
+    // parallelogram with a corner at `corner` and sides `u` and `v`
+    Quad {
+        corner : Vec3,
+        u : Vec3,
+        v : Vec3,
+        material : String,
+        #[serde(default)]
+        transform : Option<TransformDesc>,
+    },
+    // axis aligned box between two opposite corners
+    Box {
+        min : Vec3,
+        max : Vec3,
+        material : String,
+        #[serde(default)]
+        transform : Option<TransformDesc>,
+    },
+    // OBJ file, relative paths are resolved from the scene file directory. Materials come from its MTL files.
+    // Placing the same file again shares the triangles.
+    Mesh {
+        path : PathBuf,
+        #[serde(default)]
+        transform : Option<TransformDesc>,
+    },
+}
+
+// applied in this order: scale, then the rotations around x, y and z in degrees, then the translation
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TransformDesc {
+    scale : Vec3,
+    rotate : Vec3,
+    translate : Vec3,
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        TransformDesc { scale : Vec3::one(), rotate : Vec3::zero(), translate : Vec3::zero() }
+    }
+}
+
+impl TransformDesc {
+    fn build(&self) -> Result<Transform, String> {
+        if self.scale.x == 0.0 || self.scale.y == 0.0 || self.scale.z == 0.0 {
+            return Err(format!("transform.scale can't be 0 along any axis, found {:?}", self.scale));
+        }
+        Ok(Transform::scale(self.scale)
+            .then(&Transform::rotate(0, self.rotate.x))
+            .then(&Transform::rotate(1, self.rotate.y))
+            .then(&Transform::rotate(2, self.rotate.z))
+            .then(&Transform::translate(self.translate)))
+    }
+}
+
+fn default_time1() -> f32 {
+    1.0
+}
+
+fn line_of(source : &str, offset : usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+// Parses a scene description, `path` is only used for error messages and to resolve mesh paths.
+pub fn parse_scene(source : &str, path : &Path) -> Result<Scene, SceneError> {
+    let file : SceneFile = toml::from_str(source).map_err(|e| SceneError::Parse(path.to_path_buf(), e))?;
+
+    let invalid = |line : Option<usize>, message : String| SceneError::Invalid { path : path.to_path_buf(), line, message };
+    // for what renders, but not as well as it could
+    let warn = |line : Option<usize>, message : String| match line {
+        Some(line) => eprintln!("warning: {}:{}: {}", path.display(), line, message),
+        None => eprintln!("warning: {}: {}", path.display(), message),
+    };
+    let unsampled_light = |index : usize, what : &str| {
+        format!("objects[{}]: {} isn't sampled as a light, only the rays that hit it find it", index, what)
+    };
+    let line_of_span = |span : std::ops::Range<usize>| Some(line_of(source, span.start));
+
+    // the defaults don't need checking, so there's always a line when something is wrong
+    let render_line = file.render.as_ref().and_then(|r| line_of_span(r.span()));
+    let render = file.render.map(Spanned::into_inner).unwrap_or_default();
+    render.validate().map_err(|message| invalid(render_line, message))?;
+
+    let camera_line = line_of_span(file.camera.span());
+    let camera = file.camera.into_inner();
+    if camera.vfov <= 0.0 || camera.vfov >= 180.0 {
+        return Err(invalid(camera_line, format!("camera.vfov must be between 0 and 180 degrees, found {}", camera.vfov)));
+    }
+    // the camera frame is built from the view direction and vup, these leave it without one
+    let view = camera.look_at - camera.look_from;
+    if view.length_squared() == 0.0 {
+        return Err(invalid(camera_line, format!("camera.look_from and look_at can't be the same point, found {:?}", camera.look_from)));
+    }
+    let sine = Vec3::cross(&view, &camera.vup).length() / (view.length() * camera.vup.length());
+    if sine.is_nan() || sine <= 1e-6 {
+        return Err(invalid(camera_line, format!("camera.vup can't be parallel to the view direction, found {:?}", camera.vup)));
+    }
+    if camera.aperture.is_nan() || camera.aperture < 0.0 {
+        return Err(invalid(camera_line, format!("camera.aperture must be 0 or more, found {}", camera.aperture)));
+    }
+    if let Some(focus_dist) = camera.focus_dist {
+        if focus_dist.is_nan() || focus_dist <= 0.0 {
+            return Err(invalid(camera_line, format!("camera.focus_dist must be greater than 0, found {}", focus_dist)));
+        }
+    }
+    if camera.shutter_close < camera.shutter_open {
+        return Err(invalid(camera_line, format!("camera.shutter_close can't be before shutter_open, found {} and {}", camera.shutter_close, camera.shutter_open)));
+    }
+
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut objects : Vec<Box<dyn Hittable + Send + Sync>> = Vec::new();
+
+    let mut textures = HashMap::new();
+    for (name, desc) in &file.textures {
+        let texture = desc.get_ref().build(base_dir).map_err(|message| invalid(line_of_span(desc.span()), format!("textures.{}: {}", name, message)))?;
+        textures.insert(name.clone(), texture);
+    }
+
+    let mut materials = HashMap::new();
+    for (name, desc) in &file.materials {
+        let material = desc.get_ref().build(&textures).map_err(|message| invalid(line_of_span(desc.span()), format!("materials.{}: {}", name, message)))?;
+        materials.insert(name.clone(), material);
+    }
+
+    // meshes loaded so far, to be placed again without loading them twice
+    let mut meshes : HashMap<PathBuf, Arc<dyn Hittable + Send + Sync>> = HashMap::new();
+
+    for (index, obj) in file.objects.into_iter().enumerate() {
+        let line = line_of_span(obj.span());
+
+        let find_material = |name : &String| {
+            materials.get(name).map(|m| m.clone_box())
+                .ok_or_else(|| invalid(line, format!("objects[{}]: unknown material `{}`", index, name)))
+        };
+        let build_transform = |desc : &TransformDesc| {
+            desc.build().map_err(|message| invalid(line, format!("objects[{}]: {}", index, message)))
+        };
+
+        let (mut shapes, transform) : (Vec<Box<dyn Hittable + Send + Sync>>, _) = match obj.into_inner() {
+            ObjectDesc::Sphere { center, radius, material, transform } => {
+                (vec![ Box::new( Sphere::new(center, radius, find_material(&material)?) ) ], transform)
+            },
+            ObjectDesc::MovingSphere { center0, center1, time0, time1, radius, material, transform } => {
+                let material = find_material(&material)?;
+                if material.is_emissive() {
+                    warn(line, unsampled_light(index, "an emissive moving_sphere"));
+                }
+                (vec![ Box::new( MovingSphere { center0, center1, time0, time1, radius, material } ) ], transform)
+            },
+            ObjectDesc::Triangle { vertices, material, transform } => {
+                (vec![ Box::new( Triangle::new(vertices[0], vertices[1], vertices[2], find_material(&material)?) ) ], transform)
+            },
+            ObjectDesc::Quad { corner, u, v, material, transform } => {
+                (quad(corner, u, v, find_material(&material)?), transform)
+            },
+            ObjectDesc::Box { min, max, material, transform } => {
+                (block(min, max, find_material(&material)?), transform)
+            },
+            ObjectDesc::Mesh { path : mesh_path, transform } => {
+                let mesh_path = base_dir.join(mesh_path);
+                let mesh = match meshes.get(&mesh_path) {
+                    Some(mesh) => mesh.clone(),
+                    None => {
+                        let mesh : Arc<dyn Hittable + Send + Sync> = Arc::new(BvhNode::new(obj::load_obj(&mesh_path).map_err(SceneError::Mesh)?));
+                        meshes.insert(mesh_path, mesh.clone());
+                        mesh
+                    },
+                };
+                match transform {
+                    Some(transform) => {
+                        let transform = build_transform(&transform)?;
+                        if !transform.is_similarity() && !mesh.lights().is_empty() {
+                            warn(line, unsampled_light(index, "an emissive object scaled unevenly"));
+                        }
+                        objects.push( Box::new( Transformed::new(mesh, transform) ));
+                    },
+                    None => objects.push( Box::new(mesh) ),
+                }
+                continue;
+            },
+        };
+
+        // the shapes of one object are moved together, under a BVH when there are several
+        match transform {
+            None => objects.extend(shapes),
+            Some(transform) => {
+                let transform = build_transform(&transform)?;
+                if !transform.is_similarity() && shapes.iter().any(|shape| !shape.lights().is_empty()) {
+                    warn(line, unsampled_light(index, "an emissive object scaled unevenly"));
+                }
+                let shapes : Arc<dyn Hittable + Send + Sync> = match shapes.len() {
+                    1 => Arc::from(shapes.pop().unwrap()),
+                    _ => Arc::new(BvhNode::new(shapes)),
+                };
+                objects.push( Box::new( Transformed::new(shapes, transform) ));
+            },
+        }
+    }
+
+    Ok(Scene {
+        camera,
+        render,
+        background : file.background,
+        tonemap : file.tonemap,
+        objects,
+    })
+}
+
+pub fn load_scene(path : &Path) -> Result<Scene, SceneError> {
+    let source = std::fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+    parse_scene(&source, path)
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::filter::FilterKind;
+
+    const SCENE : &str = r#"
+        [camera]
+        look_from = [0, 0, 1]
+        look_at = [0, 0, -1]
+        vfov = 90
+
+        [render]
+        width = 20
+        height = 10
+        samples_per_pixel = 4
+        max_depth = 8
+
+        [materials.gray]
+        type = "lambertian"
+        albedo = [0.5, 0.5, 0.5]
+
+        [materials.glass]
+        type = "dielectric"
+        ir = 1.5
+
+        [[objects]]
+        type = "sphere"
+        center = [0, 0, -1]
+        radius = 0.5
+        material = "gray"
+
+        [[objects]]
+        type = "triangle"
+        vertices = [[-1, 0, -2], [1, 0, -2], [0, 1, -2]]
+        material = "glass"
+    "#;
+
+    #[test]
+    fn scene_parse(){
+        let scene = parse_scene(SCENE, Path::new("test.toml")).unwrap();
+        assert_eq!(scene.render.width, 20);
+        assert_eq!(scene.render.samples_per_pixel, 4);
+        assert!(!scene.render.denoise);
+        assert_eq!(scene.objects.len(), 2);
+
+        let denoised = SCENE.replace("max_depth = 8", "max_depth = 8\ndenoise = true");
+        assert!(parse_scene(&denoised, Path::new("test.toml")).unwrap().render.denoise);
+        assert_eq!(scene.render.adaptive, None);
+
+        let adaptive = SCENE.replace("[materials.gray]", "[render.adaptive]\nmax_samples = 64\n\n[materials.gray]");
+        let scene = parse_scene(&adaptive, Path::new("test.toml")).unwrap();
+        assert_eq!(scene.render.adaptive, Some(AdaptiveSampling { max_samples : 64, ..AdaptiveSampling::default() }));
+        assert_eq!(scene.render.progressive, None);
+
+        let progressive = SCENE.replace("[materials.gray]", "[render.progressive]\nsnapshot_interval = 30\n\n[materials.gray]");
+        let scene = parse_scene(&progressive, Path::new("test.toml")).unwrap();
+        assert_eq!(scene.render.progressive, Some(Progressive { pass_samples : 4, snapshot_interval : Some(30.0) }));
+        assert_eq!(scene.render.sampler, SamplerKind::Independent);
+
+        let sobol = SCENE.replace("max_depth = 8", "max_depth = 8\nsampler = \"sobol\"");
+        assert_eq!(parse_scene(&sobol, Path::new("test.toml")).unwrap().render.sampler, SamplerKind::Sobol);
+
+        let gaussian = SCENE.replace("[materials.gray]", "[render.filter]\nkind = \"gaussian\"\nradius = 2\n\n[materials.gray]");
+        let scene = parse_scene(&gaussian, Path::new("test.toml")).unwrap();
+        assert_eq!(scene.render.filter, Filter { kind : FilterKind::Gaussian, radius : Some(2.0) });
+        assert!(parse_scene(&gaussian.replace("radius = 2", "radius = 0.1"), Path::new("test.toml")).is_err());
+
+        let moving = r#"
+        [[objects]]
+        type = "moving_sphere"
+        center0 = [0, 0, -1]
+        center1 = [0, 1, -1]
+        radius = 0.5
+        material = "gray"
+        "#;
+        let source = SCENE.replace("vfov = 90", "vfov = 90\nshutter_close = 0.5") + moving;
+        let scene = parse_scene(&source, Path::new("test.toml")).unwrap();
+        assert_eq!(scene.objects.len(), 3);
+        assert_eq!((scene.camera.shutter_open, scene.camera.shutter_close), (0.0, 0.5));
+        let bbox = scene.objects[2].bounding_box();
+        assert_eq!((bbox.min.y, bbox.max.y), (-0.5, 1.5));
+        let reversed = source.replace("shutter_close = 0.5", "shutter_open = 1.0\nshutter_close = 0.5");
+        assert!(parse_scene(&reversed, Path::new("test.toml")).is_err());
+
+        // left out of the light sampling, with a warning
+        let lamp = "[materials.lamp]\ntype = \"diffuse_light\"\nemit = [4, 4, 4]\n\n[materials.gray]";
+        let light = SCENE.replace("[materials.gray]", lamp) + &moving.replace("gray", "lamp");
+        let scene = parse_scene(&light, Path::new("test.toml")).unwrap();
+        assert!(scene.objects[2].lights().is_empty());
+    }
+
+    #[test]
+    fn scene_transforms(){
+        let dir = std::env::temp_dir().join(format!("raytracer-{}-transforms", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tri.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+
+        // the same mesh twice, and a box turned around y
+        let objects = r#"
+        [[objects]]
+        type = "mesh"
+        path = "tri.obj"
+        transform = { translate = [0, 0, -5] }
+
+        [[objects]]
+        type = "mesh"
+        path = "tri.obj"
+        transform = { scale = [2, 2, 2], rotate = [0, 0, 90], translate = [10, 0, 0] }
+
+        [[objects]]
+        type = "box"
+        min = [-1, 0, -1]
+        max = [1, 1, 1]
+        material = "gray"
+        transform = { rotate = [0, 45, 0] }
+
+        [[objects]]
+        type = "mesh"
+        path = "tri.obj"
+
+        [[objects]]
+        type = "sphere"
+        center = [0, 0, 0]
+        radius = 1
+        material = "gray"
+        transform = { scale = [1, 2, 1], translate = [0, 0, -3] }
+
+        [[objects]]
+        type = "quad"
+        corner = [0, 0, 0]
+        u = [1, 0, 0]
+        v = [0, 1, 0]
+        material = "gray"
+        transform = { rotate = [90, 0, 0] }
+        "#;
+        let scene = parse_scene(&(SCENE.to_string() + objects), &dir.join("test.toml")).unwrap();
+        assert_eq!(scene.objects.len(), 8);
+
+        let close = |a : Vec3, b : Vec3| (a - b).length() < 1e-3;
+        let first = scene.objects[2].bounding_box();
+        assert!(close(first.min, Vec3::new(0.0, 0.0, -5.0)) && close(first.max, Vec3::new(1.0, 1.0, -5.0)));
+        let second = scene.objects[3].bounding_box();
+        assert!(close(second.min, Vec3::new(8.0, 0.0, 0.0)) && close(second.max, Vec3::new(10.0, 2.0, 0.0)), "{:?}", second);
+        let turned = scene.objects[4].bounding_box();
+        assert!((turned.max.x - 2.0f32.sqrt()).abs() < 1e-3 && (turned.max.y - 1.0).abs() < 1e-3);
+        let plain = scene.objects[5].bounding_box();
+        assert!(close(plain.min, Vec3::zero()) && close(plain.max, Vec3::new(1.0, 1.0, 0.0)));
+        let stretched = scene.objects[6].bounding_box();
+        assert!(close(stretched.min, Vec3::new(-1.0, -2.0, -4.0)) && close(stretched.max, Vec3::new(1.0, 2.0, -2.0)), "{:?}", stretched);
+        // the two triangles of the quad turned flat onto the floor
+        let quad = scene.objects[7].bounding_box();
+        assert!((quad.max.y - quad.min.y).abs() < 1e-3 && (quad.max.z - quad.min.z - 1.0).abs() < 1e-3, "{:?}", quad);
+
+        // stretched lights aren't sampled, with a warning, scaled evenly they are
+        let lamp = "[materials.lamp]\ntype = \"diffuse_light\"\nemit = [4, 4, 4]\n\n[materials.gray]";
+        let lit = SCENE.replace("[materials.gray]", lamp) + &objects.replace("material = \"gray\"", "material = \"lamp\"");
+        let scene = parse_scene(&lit, &dir.join("test.toml")).unwrap();
+        assert!(scene.objects[6].lights().is_empty());
+        let even = lit.replace("scale = [1, 2, 1]", "scale = [2, 2, 2]");
+        assert_eq!(parse_scene(&even, &dir.join("test.toml")).unwrap().objects[6].lights().len(), 1);
+
+        let flat = SCENE.to_string() + &objects.replace("scale = [2, 2, 2]", "scale = [2, 0, 2]");
+        assert!(matches!(parse_scene(&flat, &dir.join("test.toml")), Err(SceneError::Invalid { .. })));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scene_example_files(){
+        for entry in std::fs::read_dir("scenes").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "toml") {
+                if let Err(err) = load_scene(&path) {
+                    panic!("{}", err);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scene_unknown_material(){
+        let source = SCENE.replace("material = \"glass\"", "material = \"glas\"");
+        let err = parse_scene(&source, Path::new("test.toml")).err().unwrap();
+        assert_eq!(err.to_string(), "test.toml:27: objects[1]: unknown material `glas`");
+    }
+
+    #[test]
+    fn scene_textures(){
+        let checker = "
+        [textures.checker]
+        type = \"checker\"
+        scale = 0.5
+        even = [0, 0, 0]
+        odd = [1, 1, 1]
+        ";
+        let source = SCENE.replace("albedo = [0.5, 0.5, 0.5]", "albedo = \"checker\"") + checker;
+        let scene = parse_scene(&source, Path::new("test.toml")).unwrap();
+        assert_eq!(scene.objects.len(), 2);
+
+        let marble = "
+        [textures.marble]
+        type = \"noise\"
+        pattern = \"marble\"
+        scale = 4
+        seed = 3
+        ";
+        let source = SCENE.replace("albedo = [0.5, 0.5, 0.5]", "albedo = \"marble\"") + marble;
+        assert!(parse_scene(&source, Path::new("test.toml")).is_ok());
+
+        let source = SCENE.replace("albedo = [0.5, 0.5, 0.5]", "albedo = \"checkers\"") + checker;
+        let err = parse_scene(&source, Path::new("test.toml")).err().unwrap();
+        assert_eq!(err.to_string(), "test.toml:13: materials.gray: unknown texture `checkers`");
+
+        let source = SCENE.replace("albedo = [0.5, 0.5, 0.5]", "albedo = 0.5");
+        let err = parse_scene(&source, Path::new("test.toml")).err().unwrap().to_string();
+        assert!(err.contains("a colour [r, g, b] or the name of a texture"), "{}", err);
+
+        let source = SCENE.to_string() + "[textures.wood]\ntype = \"image\"\npath = \"missing.png\"\n";
+        let err = parse_scene(&source, Path::new("test.toml")).err().unwrap().to_string();
+        assert!(err.starts_with("test.toml:31: textures.wood: can't load `missing.png`"), "{}", err);
+    }
+
+    #[test]
+    fn scene_bad_value(){
+        let source = SCENE.replace("vfov = 90", "vfov = \"wide\"");
+        let err = parse_scene(&source, Path::new("test.toml")).err().unwrap().to_string();
+        assert!(err.contains("line 5, column 16"), "{}", err);
+        assert!(err.contains("invalid type: string \"wide\", expected f32"), "{}", err);
+
+        let source = SCENE.replace("radius = 0.5", "radius = 0.5\n        colour = [1, 0, 0]");
+        let err = parse_scene(&source, Path::new("test.toml")).err().unwrap().to_string();
+        assert!(err.contains("line 21"), "{}", err);
+        assert!(err.contains("unknown field `colour`"), "{}", err);
+
+        let source = SCENE.replace("ir = 1.5", "");
+        let err = parse_scene(&source, Path::new("test.toml")).err().unwrap().to_string();
+        assert!(err.contains("line 17"), "{}", err);
+        assert!(err.contains("missing field `ir`"), "{}", err);
+
+        // checked after parsing, at the line of their table
+        let source = SCENE.replace("max_depth = 8", "max_depth = 0");
+        let err = parse_scene(&source, Path::new("test.toml")).err().unwrap().to_string();
+        assert_eq!(err, "test.toml:7: render.max_depth must be greater than 0, found 0");
+
+        let bad_render = [
+            ("[render.progressive]\npass_samples = 0", "render.progressive.pass_samples must be greater than 0, found 0"),
+            ("[render.progressive]\nsnapshot_interval = -1", "render.progressive.snapshot_interval must be 0 or more seconds, found -1"),
+            ("[render.adaptive]\nmin_samples = 64\nmax_samples = 16", "render.adaptive needs 0 < min_samples <= max_samples, found 64 and 16"),
+            ("[render.adaptive]\nmax_samples = 0", "render.adaptive needs 0 < min_samples <= max_samples, found 16 and 0"),
+            ("[render.adaptive]\nthreshold = nan", "render.adaptive.threshold must be 0 or more, found NaN"),
+            ("[render.filter]\nradius = nan", "render.filter.radius must be between 0.5 and 16, found NaN"),
+            ("[render.filter]\nradius = 100", "render.filter.radius must be between 0.5 and 16, found 100"),
+        ];
+        for (table, message) in bad_render {
+            let source = SCENE.replace("[materials.gray]", &format!("{}\n\n[materials.gray]", table));
+            let err = parse_scene(&source, Path::new("test.toml")).err().unwrap().to_string();
+            assert_eq!(err, format!("test.toml:7: {}", message));
+        }
+
+        let source = SCENE.replace("vfov = 90", "vfov = 180");
+        let err = parse_scene(&source, Path::new("test.toml")).err().unwrap().to_string();
+        assert_eq!(err, "test.toml:2: camera.vfov must be between 0 and 180 degrees, found 180");
+
+        let bad_camera = [
+            ("look_at = [0, 0, -1]", "look_at = [0, 0, 1]", "camera.look_from and look_at can't be the same point, found Vec3 { x: 0.0, y: 0.0, z: 1.0 }"),
+            ("vfov = 90", "vfov = 90\nvup = [0, 0, 2]", "camera.vup can't be parallel to the view direction, found Vec3 { x: 0.0, y: 0.0, z: 2.0 }"),
+            ("vfov = 90", "vfov = 90\naperture = -0.1", "camera.aperture must be 0 or more, found -0.1"),
+            ("vfov = 90", "vfov = 90\nfocus_dist = 0", "camera.focus_dist must be greater than 0, found 0"),
+        ];
+        for (from, to, message) in bad_camera {
+            let err = parse_scene(&SCENE.replace(from, to), Path::new("test.toml")).err().unwrap().to_string();
+            assert_eq!(err, format!("test.toml:2: {}", message));
+        }
+    }
+}
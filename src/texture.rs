@@ -0,0 +1,210 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::vec::Vec3;
+use crate::noise::{Perlin, Worley};
+
+// Colour that varies over a surface, looked up with the (u, v) coordinates of the hit and its position.
+pub trait Texture : Send + Sync {
+    fn value(&self, u : f32, v : f32, p : &Vec3) -> Vec3;
+}
+
+pub struct SolidColor {
+    pub color : Vec3,
+}
+
+impl SolidColor {
+    // ready to be used as a material albedo
+    pub fn shared(color : Vec3) -> Arc<dyn Texture> {
+        Arc::new( SolidColor{ color } )
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u : f32, _v : f32, _p : &Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+// Alternates between two textures in cubes of `scale` units, based on the position so it
+// doesn't depend on how the surface is parametrized.
+pub struct CheckerTexture {
+    pub scale : f32,
+    pub even : Arc<dyn Texture>,
+    pub odd : Arc<dyn Texture>,
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u : f32, v : f32, p : &Vec3) -> Vec3 {
+        let x = (p.x / self.scale).floor() as i64;
+        let y = (p.y / self.scale).floor() as i64;
+        let z = (p.z / self.scale).floor() as i64;
+
+        if (x + y + z) % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+// Image mapped over the (u, v) square, with (0, 0) at the bottom left corner. Repeats outside of it.
+pub struct ImageTexture {
+    width : usize,
+    height : usize,
+    // linear colours, rows from top to bottom
+    pixels : Vec<Vec3>,
+}
+
+impl ImageTexture {
+    pub fn load(path : &Path) -> Result<Self, image::ImageError> {
+        let img = image::open(path)?.to_rgb8();
+        let (width, height) = img.dimensions();
+
+        let pixels = img.pixels()
+            .map(|p| Vec3::new(srgb_to_linear(p[0]), srgb_to_linear(p[1]), srgb_to_linear(p[2])))
+            .collect();
+
+        Ok(ImageTexture {
+            width : width as usize,
+            height : height as usize,
+            pixels,
+        })
+    }
+}
+
+// image files store gamma encoded colours, the renderer works with linear ones
+fn srgb_to_linear(c : u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u : f32, v : f32, _p : &Vec3) -> Vec3 {
+        if self.pixels.is_empty() {
+            return Vec3::new(1.0, 0.0, 1.0);
+        }
+
+        let u = u - u.floor();
+        let v = 1.0 - (v - v.floor());
+
+        let i = ((u * self.width as f32) as usize).min(self.width - 1);
+        let j = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[i + j * self.width]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoisePattern {
+    Perlin,
+    Turbulence,
+    // veins along z, perturbed by turbulence
+    Marble,
+    // rings around the y axis
+    Wood,
+    // cells, dark at their centres
+    Worley,
+}
+
+// Procedural texture blending between `low` and `high` following a noise pattern. Positions are
+// multiplied by `scale`, so bigger scales make smaller features. The same seed gives the same texture.
+pub struct NoiseTexture {
+    pattern : NoisePattern,
+    scale : f32,
+    low : Vec3,
+    high : Vec3,
+    perlin : Perlin,
+    worley : Worley,
+}
+
+impl NoiseTexture {
+    pub fn new(pattern : NoisePattern, scale : f32, low : Vec3, high : Vec3, seed : u64) -> Self {
+        NoiseTexture {
+            pattern,
+            scale,
+            low,
+            high,
+            perlin : Perlin::new(seed),
+            worley : Worley::new(seed),
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u : f32, _v : f32, p : &Vec3) -> Vec3 {
+        let sp = *p * self.scale;
+
+        let t = match self.pattern {
+            NoisePattern::Perlin => 0.5 * (1.0 + self.perlin.noise(&sp)),
+            NoisePattern::Turbulence => self.perlin.turbulence(&sp, 7),
+            // the turbulence is kept at the original scale, `scale` only changes how close the veins and rings are
+            NoisePattern::Marble => 0.5 * (1.0 + (sp.z + 10.0 * self.perlin.turbulence(p, 7)).sin()),
+            NoisePattern::Wood => {
+                let rings = (sp.x * sp.x + sp.z * sp.z).sqrt() + self.perlin.turbulence(p, 4);
+                rings - rings.floor()
+            },
+            NoisePattern::Worley => self.worley.distance(&sp),
+        };
+
+        let t = t.clamp(0.0, 1.0);
+        self.low * (1.0 - t) + self.high * t
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn checker_texture(){
+        let checker = CheckerTexture {
+            scale : 0.5,
+            even : SolidColor::shared(Vec3::zero()),
+            odd : SolidColor::shared(Vec3::one()),
+        };
+
+        assert_eq!(checker.value(0.0, 0.0, &Vec3::new(0.1, 0.1, 0.1)), Vec3::zero());
+        assert_eq!(checker.value(0.0, 0.0, &Vec3::new(0.6, 0.1, 0.1)), Vec3::one());
+        assert_eq!(checker.value(0.0, 0.0, &Vec3::new(-0.1, 0.1, 0.1)), Vec3::one());
+        assert_eq!(checker.value(0.0, 0.0, &Vec3::new(0.6, -0.1, 0.1)), Vec3::zero());
+    }
+
+    #[test]
+    fn noise_texture(){
+        let patterns = [NoisePattern::Perlin, NoisePattern::Turbulence, NoisePattern::Marble, NoisePattern::Wood, NoisePattern::Worley];
+        for pattern in patterns {
+            let texture = NoiseTexture::new(pattern, 4.0, Vec3::zero(), Vec3::one(), 3);
+            let same = NoiseTexture::new(pattern, 4.0, Vec3::zero(), Vec3::one(), 3);
+
+            for i in 0..100 {
+                let p = Vec3::new(i as f32 * 0.13, (i as f32 * 0.7).sin(), 0.5);
+                let c = texture.value(0.0, 0.0, &p);
+                assert!(c.x >= 0.0 && c.x <= 1.0 && c.x == c.y && c.y == c.z, "{:?} {:?}", pattern, c);
+                assert_eq!(c, same.value(0.0, 0.0, &p));
+            }
+        }
+    }
+
+    #[test]
+    fn image_texture(){
+        // 2x2 image, red and green on top, blue and white at the bottom
+        let texture = ImageTexture {
+            width : 2,
+            height : 2,
+            pixels : vec![Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec3::one()],
+        };
+
+        let p = Vec3::zero();
+        assert_eq!(texture.value(0.25, 0.75, &p), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(texture.value(0.75, 0.75, &p), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(texture.value(0.25, 0.25, &p), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(texture.value(1.75, -0.75, &p), Vec3::one());
+        assert_eq!(texture.value(1.0, 1.0, &p), Vec3::new(0.0, 0.0, 1.0));
+        assert!((srgb_to_linear(255) - 1.0).abs() < 1e-6);
+        assert!((srgb_to_linear(128) - 0.2158).abs() < 1e-3);
+    }
+}
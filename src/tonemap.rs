@@ -0,0 +1,125 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::color::Color;
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapper {
+    // anything above 1 is cut off
+    Clamp,
+    // c / (1 + c), never quite reaches white
+    Reinhard,
+    // Reinhard that maps the white point to 1
+    ExtendedReinhard,
+    // Narkowicz's fit of the ACES filmic curve
+    Aces,
+    // John Hable's Uncharted 2 filmic curve
+    Hable,
+}
+
+// How the linear radiance of the film is turned into 8 bit colours, float outputs are written untouched.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToneMapping {
+    // in stops, every +1 doubles the brightness
+    pub exposure : f32,
+    pub tone_mapper : ToneMapper,
+    // radiance that ends up white, for extended_reinhard and hable
+    pub white_point : f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping {
+            exposure : 0.0,
+            tone_mapper : ToneMapper::Clamp,
+            white_point : 4.0,
+        }
+    }
+}
+
+impl ToneMapping {
+    // exposure, tone curve and sRGB encoding
+    pub fn apply(&self, c : &Color<f32>) -> Color<u8> {
+        let scale = self.exposure.exp2();
+        Color::new_from_f32(
+            srgb_oetf(self.map(c.r * scale)),
+            srgb_oetf(self.map(c.g * scale)),
+            srgb_oetf(self.map(c.b * scale)),
+        )
+    }
+
+    // a single channel, the result is between 0 and 1
+    fn map(&self, x : f32) -> f32 {
+        // negative or NaN values come out as black
+        let x = if x > 0.0 { x } else { 0.0 };
+        let white = self.white_point.max(1e-3);
+
+        let mapped = match self.tone_mapper {
+            ToneMapper::Clamp => x,
+            ToneMapper::Reinhard => x / (1.0 + x),
+            ToneMapper::ExtendedReinhard => x * (1.0 + x / (white * white)) / (1.0 + x),
+            ToneMapper::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            ToneMapper::Hable => hable(x) / hable(white),
+        };
+        mapped.min(1.0)
+    }
+}
+
+fn hable(x : f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+// linear to sRGB encoded, both between 0 and 1
+pub fn srgb_oetf(c : f32) -> f32 {
+    if c <= 0.0031308 { 12.92 * c } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn tone_mappers(){
+        let mappers = [ToneMapper::Clamp, ToneMapper::Reinhard, ToneMapper::ExtendedReinhard, ToneMapper::Aces, ToneMapper::Hable];
+        for tone_mapper in mappers {
+            let tonemap = ToneMapping { tone_mapper, ..ToneMapping::default() };
+            assert!(tonemap.map(0.0).abs() < 1e-6);
+            assert!(tonemap.map(-1.0).abs() < 1e-6);
+            assert!(tonemap.map(f32::NAN).abs() < 1e-6);
+
+            // increasing and never above white
+            let mut last = 0.0;
+            for i in 1..200 {
+                let y = tonemap.map(i as f32 * 0.05);
+                assert!(y >= last && y <= 1.0, "{:?}", tone_mapper);
+                last = y;
+            }
+        }
+
+        let extended = ToneMapping { tone_mapper : ToneMapper::ExtendedReinhard, white_point : 3.0, ..ToneMapping::default() };
+        assert!((extended.map(3.0) - 1.0).abs() < 1e-6);
+        let hable = ToneMapping { tone_mapper : ToneMapper::Hable, white_point : 3.0, ..ToneMapping::default() };
+        assert!((hable.map(3.0) - 1.0).abs() < 1e-6);
+        let reinhard = ToneMapping { tone_mapper : ToneMapper::Reinhard, ..ToneMapping::default() };
+        assert_eq!(reinhard.map(1.0), 0.5);
+    }
+
+    #[test]
+    fn exposure_and_srgb(){
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_oetf(0.21404) - 0.5).abs() < 1e-4);
+
+        let tonemap = ToneMapping::default();
+        let c = tonemap.apply(&Color::new(0.25, 2.0, 0.0));
+        assert_eq!((c.r, c.g, c.b), (137, 255, 0));
+
+        let brighter = ToneMapping { exposure : 2.0, ..ToneMapping::default() };
+        let c = brighter.apply(&Color::new(0.25, 0.0, 0.0));
+        assert_eq!(c.r, 255);
+    }
+}
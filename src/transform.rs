@@ -0,0 +1,289 @@
+use std::sync::Arc;
+
+use crate::vec::Vec3;
+use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::hitrecord::{HitRecord, Hittable};
+use crate::sampler::Sampler;
+
+type Matrix = [[f32; 4]; 4];
+
+const IDENTITY : Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+// Affine 4x4 transform, with its inverse kept alongside so it never has to be computed. Only built from
+// translations, rotations and scales, which are easy to invert one by one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    m : Matrix,
+    inv : Matrix,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform { m : IDENTITY, inv : IDENTITY }
+    }
+
+    pub fn translate(v : Vec3) -> Self {
+        let mut m = IDENTITY;
+        let mut inv = IDENTITY;
+        for axis in 0..3 {
+            m[axis][3] = v[axis];
+            inv[axis][3] = -v[axis];
+        }
+        Transform { m, inv }
+    }
+
+    // a scale of 0 along any axis can't be undone
+    pub fn scale(v : Vec3) -> Self {
+        let mut m = IDENTITY;
+        let mut inv = IDENTITY;
+        for axis in 0..3 {
+            m[axis][axis] = v[axis];
+            inv[axis][axis] = 1.0 / v[axis];
+        }
+        Transform { m, inv }
+    }
+
+    // counterclockwise when looking down the axis towards the origin, in degrees
+    pub fn rotate(axis : usize, degrees : f32) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut m = IDENTITY;
+        m[a][a] = cos;
+        m[a][b] = -sin;
+        m[b][a] = sin;
+        m[b][b] = cos;
+        // rotations are orthogonal, the inverse is the transpose
+        Transform { m, inv : transpose(&m) }
+    }
+
+    // this transform followed by `other`
+    pub fn then(&self, other : &Transform) -> Transform {
+        Transform { m : multiply(&other.m, &self.m), inv : multiply(&self.inv, &other.inv) }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform { m : self.inv, inv : self.m }
+    }
+
+    pub fn point(&self, p : &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    pub fn vector(&self, v : &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    // normals go through the inverse transpose so they stay perpendicular to the surface, not normalized
+    pub fn normal(&self, n : &Vec3) -> Vec3 {
+        let inv = &self.inv;
+        Vec3::new(
+            inv[0][0] * n.x + inv[1][0] * n.y + inv[2][0] * n.z,
+            inv[0][1] * n.x + inv[1][1] * n.y + inv[2][1] * n.z,
+            inv[0][2] * n.x + inv[1][2] * n.y + inv[2][2] * n.z,
+        )
+    }
+
+    // the direction isn't normalized, so distances along the ray stay the same on both sides
+    pub fn ray(&self, r : &Ray) -> Ray {
+        Ray::new_at(self.point(&r.origin), self.vector(&r.dir), r.time)
+    }
+
+    // around the 8 transformed corners
+    pub fn bbox(&self, b : &Aabb) -> Aabb {
+        if b.is_empty() {
+            return *b;
+        }
+        let corners : Vec<Vec3> = (0..8).map(|i| {
+            let pick = |bit : usize, axis : usize| if i & bit == 0 { b.min[axis] } else { b.max[axis] };
+            self.point(&Vec3::new(pick(1, 0), pick(2, 1), pick(4, 2)))
+        }).collect();
+        Aabb::from_points(&corners)
+    }
+
+    // only rotates, translates and scales the same along every axis, so angles and solid angles seen
+    // from a transformed point don't change
+    pub fn is_similarity(&self) -> bool {
+        let columns : Vec<Vec3> = (0..3).map(|c| Vec3::new(self.m[0][c], self.m[1][c], self.m[2][c])).collect();
+        let length = columns[0].length_squared();
+        let tolerance = 1e-4 * length;
+        columns.iter().all(|c| (c.length_squared() - length).abs() <= tolerance)
+            && Vec3::dot(&columns[0], &columns[1]).abs() <= tolerance
+            && Vec3::dot(&columns[1], &columns[2]).abs() <= tolerance
+            && Vec3::dot(&columns[2], &columns[0]).abs() <= tolerance
+    }
+}
+
+fn multiply(a : &Matrix, b : &Matrix) -> Matrix {
+    let mut m = [[0.0; 4]; 4];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+fn transpose(a : &Matrix) -> Matrix {
+    let mut m = [[0.0; 4]; 4];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = a[j][i];
+        }
+    }
+    m
+}
+
+
+// An object placed in the scene with a transform. The object is shared, so the same mesh can be placed
+// any number of times without copying it, and rays are moved into its space instead of the other way around.
+pub struct Transformed<H : Hittable + ?Sized> {
+    object : Arc<H>,
+    transform : Transform,
+    bbox : Aabb,
+}
+
+impl<H : Hittable + ?Sized> Transformed<H> {
+    pub fn new(object : Arc<H>, transform : Transform) -> Self {
+        let bbox = transform.bbox(&object.bounding_box());
+        Transformed { object, transform, bbox }
+    }
+}
+
+impl<H : Hittable + Send + Sync + ?Sized> Hittable for Transformed<H> {
+    fn hit(&self, r : &Ray, t_min : f32, t_max : f32, hit_record : &mut HitRecord ) -> bool {
+        let local = self.transform.inverse().ray(r);
+        if !self.object.hit(&local, t_min, t_max, hit_record) {
+            return false;
+        }
+
+        // the normal already faces the ray, the inverse transpose keeps it that way
+        hit_record.p = self.transform.point(&hit_record.p);
+        hit_record.normal = Vec3::normalize(self.transform.normal(&hit_record.normal));
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    // the pdf is over the solid angle, which is the same in both spaces for similarity transforms only
+    fn pdf_value(&self, origin : &Vec3, direction : &Vec3) -> f32 {
+        let inverse = self.transform.inverse();
+        self.object.pdf_value(&inverse.point(origin), &inverse.vector(direction))
+    }
+
+    fn random(&self, origin : &Vec3, sampler : &mut dyn Sampler) -> Option<Vec3> {
+        let local = self.object.random(&self.transform.inverse().point(origin), sampler)?;
+        Some(self.transform.vector(&local))
+    }
+
+    // pdf_value is only right for similarity transforms, lights stretched unevenly are left out and the
+    // scene loader warns about them
+    fn lights(&self) -> Vec<Box<dyn Hittable + Send + Sync>> {
+        if !self.transform.is_similarity() {
+            return Vec::new();
+        }
+        self.object.lights().into_iter()
+            .map(|light| Box::new(Transformed::<dyn Hittable + Send + Sync>::new(Arc::from(light), self.transform)) as Box<dyn Hittable + Send + Sync>)
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::geometry::Sphere;
+    use crate::materials::{Lambertian, DiffuseLight};
+    use crate::sampler::SamplerKind;
+
+    fn close(a : Vec3, b : Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn transform_math(){
+        let t = Transform::scale(Vec3::new(2.0, 1.0, 1.0))
+            .then(&Transform::rotate(1, 90.0))
+            .then(&Transform::translate(Vec3::new(1.0, 2.0, 3.0)));
+
+        // x is stretched, turned to -z and moved
+        assert!(close(t.point(&Vec3::new(1.0, 0.0, 0.0)), Vec3::new(1.0, 2.0, 1.0)));
+        assert!(close(t.vector(&Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 0.0, -2.0)));
+        assert!(close(t.inverse().point(&t.point(&Vec3::new(0.3, -0.7, 5.0))), Vec3::new(0.3, -0.7, 5.0)));
+        assert!(close(Transform::rotate(2, 90.0).point(&Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 1.0, 0.0)));
+        assert!(close(Transform::rotate(0, 90.0).point(&Vec3::new(0.0, 1.0, 0.0)), Vec3::new(0.0, 0.0, 1.0)));
+
+        // normals stay perpendicular to the surface after a shear-like stretch
+        let stretch = Transform::rotate(2, 45.0).then(&Transform::scale(Vec3::new(3.0, 1.0, 1.0)));
+        let (tangent, normal) = (Vec3::new(1.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 0.0));
+        assert!(Vec3::dot(&stretch.vector(&tangent), &stretch.normal(&normal)).abs() < 1e-5);
+
+        let bbox = t.bbox(&Aabb::new(Vec3::zero(), Vec3::one()));
+        assert!(close(bbox.min, Vec3::new(1.0, 2.0, 1.0)) && close(bbox.max, Vec3::new(2.0, 3.0, 3.0)));
+
+        assert!(Transform::rotate(0, 30.0).then(&Transform::scale(Vec3::new(2.0, 2.0, 2.0))).is_similarity());
+        assert!(!t.is_similarity());
+    }
+
+    #[test]
+    fn transformed_instances(){
+        // one unit sphere placed twice
+        let sphere : Arc<dyn Hittable + Send + Sync> = Arc::new(Sphere::new(Vec3::zero(), 1.0, Box::new(Lambertian::new(Vec3::one()))));
+        let moved = Transformed::new(sphere.clone(), Transform::translate(Vec3::new(0.0, 0.0, -5.0)));
+        let squashed = Transformed::new(sphere, Transform::scale(Vec3::new(1.0, 0.5, 1.0)).then(&Transform::translate(Vec3::new(3.0, 0.0, -5.0))));
+
+        let mut rec = HitRecord::new();
+        assert!(moved.hit(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.t - 4.0).abs() < 1e-5 && close(rec.p, Vec3::new(0.0, 0.0, -4.0)));
+        assert!(close(rec.normal, Vec3::new(0.0, 0.0, 1.0)));
+
+        // the top of the squashed one is half as high, and the normal there still points up
+        let down = Ray::new(Vec3::new(3.0, 5.0, -5.0), Vec3::new(0.0, -2.0, 0.0));
+        assert!(squashed.hit(&down, 0.001, f32::INFINITY, &mut rec));
+        assert!(close(rec.p, Vec3::new(3.0, 0.5, -5.0)) && (rec.t - 2.25).abs() < 1e-5);
+        assert!(close(rec.normal, Vec3::new(0.0, 1.0, 0.0)));
+        let side = Ray::new(Vec3::new(3.0, 0.7, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(!squashed.hit(&side, 0.001, f32::INFINITY, &mut rec));
+        assert!(close(squashed.bounding_box().max, Vec3::new(4.0, 0.5, -4.0)));
+
+        // a light moved and scaled evenly is sampled where it ends up, with the same pdf
+        let light : Arc<dyn Hittable + Send + Sync> = Arc::new(Sphere::new(Vec3::zero(), 1.0, Box::new(DiffuseLight { emit : Vec3::one() })));
+        let placed = Transformed::new(light.clone(), Transform::scale(Vec3::new(2.0, 2.0, 2.0)).then(&Transform::translate(Vec3::new(0.0, 10.0, 0.0))));
+        let lights = placed.lights();
+        assert_eq!(lights.len(), 1);
+        let mut sampler = SamplerKind::Independent.build(0, 1);
+        sampler.start_sample(0, 0, 0);
+        for _ in 0..100 {
+            let dir = lights[0].random(&Vec3::zero(), sampler.as_mut()).unwrap();
+            assert!(placed.hit(&Ray::new(Vec3::zero(), dir), 0.001, f32::INFINITY, &mut rec));
+            // a sphere of radius 2 seen from 10 away
+            let cos_theta_max = (1.0f32 - 0.04).sqrt();
+            let expected = 1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_theta_max));
+            assert!((lights[0].pdf_value(&Vec3::zero(), &dir) - expected).abs() < expected * 1e-3);
+        }
+        assert!(Transformed::new(light, Transform::scale(Vec3::new(1.0, 3.0, 1.0))).lights().is_empty());
+    }
+}
@@ -1,7 +1,10 @@
 extern crate rand;
 use rand::Rng;
+use serde::Deserialize;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+// deserialized from an [x, y, z] array
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(from = "[f32; 3]")]
 pub struct Vec3{
     pub x: f32, 
     pub y : f32,
@@ -49,19 +52,17 @@ impl Vec3{
         self.x * self.x + self.y * self.y + self.z * self.z
      }
 
-    pub fn random() -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn random(rng : &mut impl Rng) -> Vec3 {
         Vec3::new( rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>() )
     }
     
-    pub fn random_in_range(min : f32, max : f32) -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn random_in_range(min : f32, max : f32, rng : &mut impl Rng) -> Vec3 {
         Vec3::new( rng.gen_range(min..max), rng.gen_range(min..max), rng.gen_range(min..max) )
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
+    pub fn random_in_unit_sphere(rng : &mut impl Rng) -> Vec3 {
        loop {
-         let p = Vec3::random();
+         let p = Vec3::random_in_range(-1.0, 1.0, rng);
          if p.length_squared() < 1.0  {
              return p;
          }
@@ -73,35 +74,57 @@ impl Vec3{
     }
 
     pub fn refract( uv : Vec3, n : Vec3, etai_over_etat : f32 ) -> Vec3{
-        let cos_theta = Vec3::dot( &(uv * -1.0), &n ).min(1.0);
+        let cos_theta = Vec3::dot( &-uv, &n ).min(1.0);
         let r_out_perp = etai_over_etat * (uv + cos_theta * n);
-        let r_out_parallel = ((1.0 - r_out_perp.length_squared()).sqrt() * -1.0) * n;
+        let r_out_parallel = -(1.0 - r_out_perp.length_squared()).sqrt() * n;
         r_out_perp + r_out_parallel
     }
 
-    pub fn random_unit_vector() -> Vec3{
-        Vec3::normalize(Vec3::random_in_unit_sphere())
+    // two unit vectors that form an orthonormal basis together with the unit vector n (Duff et al. 2017)
+    pub fn orthonormal_basis(n : &Vec3) -> (Vec3, Vec3) {
+        let sign = 1.0f32.copysign(n.z);
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+        (
+            Vec3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+            Vec3::new(b, sign + n.y * n.y * a, -n.y),
+        )
     }
+}
 
-    pub fn random_unit_circle() -> Vec3 {
-        let mut rng = rand::thread_rng();
-        
-        loop {
-            let p = Vec3::new( rng.gen::<f32>(), rng.gen::<f32>(), 0.0 );
-            if p.length_squared() > 1.0 { continue; };
 
-            return p; 
-        };
+// TRAIT implementations -----
+impl From<[f32; 3]> for Vec3{
+    fn from(v : [f32; 3]) -> Vec3{
+        Vec3::new(v[0], v[1], v[2])
     }
+}
 
-    pub fn near_zero(&self) -> bool {
-        let m = std::f32::MIN;
-        self.x.abs() < m && self.y.abs() < m && self.z.abs() < m
+impl std::ops::Neg for Vec3{
+    type Output = Vec3;
+
+    fn neg(self) -> Vec3{
+        Vec3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
     }
 }
 
+impl std::ops::Index<usize> for Vec3{
+    type Output = f32;
+
+    fn index(&self, axis : usize) -> &f32{
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", axis),
+        }
+    }
+}
 
-// TRAIT implementations -----
 impl std::ops::Add<f32> for Vec3{
     type Output = Vec3;
 
@@ -254,4 +277,17 @@ mod tests{
 
         assert_eq!( Vec3::cross(&a, &c), Vec3::zero() );
     }
+
+    #[test]
+    fn random_vectors(){
+        // every octant gets some
+        let mut rng = crate::rng::sample_rng(0, 0, 0, 0);
+        let mut octants = [0; 8];
+        for _ in 0..1000 {
+            let p = Vec3::random_in_unit_sphere(&mut rng);
+            assert!(p.length_squared() < 1.0);
+            octants[(p.x < 0.) as usize + 2 * (p.y < 0.) as usize + 4 * (p.z < 0.) as usize] += 1;
+        }
+        assert!(octants.iter().all(|n| *n > 50));
+    }
 }